@@ -0,0 +1,51 @@
+use std::{env, fs};
+
+/// Lines of an internal-json log: the file in ENV `NIXV_BENCH_LOG` when set (record one with
+/// `nix build --log-format internal-json -v 2> build.log`), otherwise a synthetic build of
+/// `lines` lines with the same mix of activities.
+pub fn recorded_log(lines: usize) -> Vec<String> {
+    if let Ok(file) = env::var("NIXV_BENCH_LOG") {
+        let content = fs::read_to_string(&file).expect("unable to read NIXV_BENCH_LOG");
+        return content.lines().map(|l| l.to_owned()).collect();
+    }
+    let mut log = Vec::with_capacity(lines);
+    let mut id = 1;
+    log.push(
+        r#"@nix {"action":"start","id":1,"level":0,"parent":0,"text":"","type":102,"fields":[]}"#
+            .to_owned(),
+    );
+    while log.len() < lines {
+        id += 2;
+        let drv = format!("/nix/store/{:032}-pkg-{}.drv", id, id);
+        log.push(format!(
+            r#"@nix {{"action":"start","id":{},"level":4,"parent":1,"text":"copying path '/nix/store/{:032}-dep-{}' from 'https://cache.nixos.org'","type":108,"fields":["/nix/store/{:032}-dep-{}","https://cache.nixos.org"]}}"#,
+            id + 1, id, id, id, id
+        ));
+        log.push(format!(
+            r#"@nix {{"action":"result","id":{},"type":105,"fields":[1024,4096,0,0]}}"#,
+            id + 1
+        ));
+        log.push(format!(r#"@nix {{"action":"stop","id":{}}}"#, id + 1));
+        log.push(format!(
+            r#"@nix {{"action":"start","id":{},"level":3,"parent":1,"text":"building '{}'","type":105,"fields":["{}","",1,1]}}"#,
+            id, drv, drv
+        ));
+        log.push(format!(
+            r#"@nix {{"action":"result","id":{},"type":104,"fields":["buildPhase"]}}"#,
+            id
+        ));
+        for n in 0..40 {
+            log.push(format!(
+                r#"@nix {{"action":"result","id":{},"type":101,"fields":["\u001b[32mcc\u001b[0m -O2 -c src/file_{}.c -o build/file_{}.o"]}}"#,
+                id, n, n
+            ));
+        }
+        log.push(format!(
+            r#"@nix {{"action":"msg","level":3,"msg":"built {}"}}"#,
+            drv
+        ));
+        log.push(format!(r#"@nix {{"action":"stop","id":{}}}"#, id));
+    }
+    log.truncate(lines);
+    log
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use nixv::nix_logs::{
+    log_sink::{activity_log_text, LogSink},
+    parser::parse,
+    types::JSONMessage,
+};
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Instant,
+};
+
+/// The writer nixv used before the log sink: one thread and one `open` per line.
+fn append_log_to_file(file_name: String, msg: String) {
+    let append = match env::var("DUMP_LOGS") {
+        Ok(value) => value.parse().unwrap_or_default(),
+        Err(_) => false,
+    };
+    if append {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(file_name + ".log")
+            .unwrap();
+        if let Err(e) = writeln!(file, "{}", msg) {
+            eprintln!("Couldn't write to file: {}", e);
+        }
+    }
+}
+
+fn main() {
+    let lines = common::recorded_log(100_000);
+    let messages: Vec<(Option<JSONMessage>, i64)> = lines.iter().map(|line| parse(line)).collect();
+    let dir = env::temp_dir().join(format!("nixv-bench-log-sink-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    env::set_current_dir(&dir).unwrap();
+    env::set_var("DUMP_LOGS", "true");
+
+    let start = Instant::now();
+    // detached like the old parser did, so finished threads release their stacks
+    let done = Arc::new(AtomicUsize::new(0));
+    let mut written = 0;
+    // both sides write the same text for the same messages
+    for (msg, id) in messages.iter() {
+        if let Some(text) = activity_log_text(msg) {
+            written += 1;
+            let log_file = "id_".to_owned() + &id.to_string();
+            let done = done.clone();
+            thread::spawn(move || {
+                append_log_to_file(log_file, text);
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+    }
+    while done.load(Ordering::SeqCst) < written {
+        thread::yield_now();
+    }
+    let spawned = start.elapsed();
+
+    let start = Instant::now();
+    let sink = LogSink::new();
+    messages.iter().for_each(|(msg, id)| sink.record(*id, msg));
+    sink.finish();
+    let single_writer = start.elapsed();
+
+    fs::remove_dir_all(&dir).unwrap();
+    println!("{} lines, {} log writes", messages.len(), written);
+    println!(
+        "thread per line: {:?} ({:.0} lines/s)",
+        spawned,
+        messages.len() as f64 / spawned.as_secs_f64()
+    );
+    println!(
+        "log sink:        {:?} ({:.0} lines/s)",
+        single_writer,
+        messages.len() as f64 / single_writer.as_secs_f64()
+    );
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use nixv::{
+    nix_logs::{parser::parse, process_logs::process_log},
+    nix_tracker::types::CommandState,
+};
+use std::time::Instant;
+
+fn main() {
+    let lines = common::recorded_log(100_000);
+    let bytes: usize = lines.iter().map(|l| l.len() + 1).sum();
+
+    let start = Instant::now();
+    let mut parsed = 0;
+    for line in lines.iter() {
+        if parse(line).0.is_some() {
+            parsed += 1;
+        }
+    }
+    let parse_only = start.elapsed();
+
+    let start = Instant::now();
+    let mut state = CommandState::new();
+    for line in lines.iter() {
+        let (res, id) = parse(line);
+        process_log(id, res, &mut state);
+    }
+    let tracked = start.elapsed();
+
+    println!("{} lines ({} parsed), {} bytes", lines.len(), parsed, bytes);
+    println!(
+        "parse:               {:?} ({:.0} lines/s, {:.1} MB/s)",
+        parse_only,
+        lines.len() as f64 / parse_only.as_secs_f64(),
+        bytes as f64 / parse_only.as_secs_f64() / 1e6
+    );
+    println!(
+        "parse + process_log: {:?} ({:.0} lines/s)",
+        tracked,
+        lines.len() as f64 / tracked.as_secs_f64()
+    );
+}
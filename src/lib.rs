@@ -1,3 +1,10 @@
+//! Insights into nix commands from their `--log-format internal-json` output.
+//!
+//! [`nix_tracker::tracker::Tracker`] is the entry point for embedding: feed it the
+//! lines nix writes to stderr, subscribe to its [`nix_tracker::events::Event`]s and
+//! read the [`nix_tracker::types::CommandState`] it builds.
+
 pub mod nix_commands;
+pub mod nix_history;
 pub mod nix_logs;
 pub mod nix_tracker;
@@ -1,12 +1,70 @@
 extern crate nixv;
+use nixv::nix_commands::attach::attach_process;
+use nixv::nix_commands::baseline::baseline_process;
+use nixv::nix_commands::build_all::build_all_process;
+use nixv::nix_commands::drv_diff::drv_diff_process;
+use nixv::nix_commands::logs::logs_process;
+use nixv::nix_commands::monitor::monitor_process;
 use nixv::nix_commands::nix_build::nix_build_process;
 use nixv::nix_commands::nix_build_flake::*;
+use nixv::nix_commands::nix_check_repro::nix_check_repro_process;
 use nixv::nix_commands::nix_develop_flake::nix_develop_flake_process;
 use nixv::nix_commands::nix_shell::nix_shell_process;
+use nixv::nix_commands::report::report_process;
+use nixv::nix_commands::view::view_process;
+use nixv::nix_commands::why::why_process;
 use nixv::nix_logs::helpers::log_;
+use nixv::nix_tracker::ci::CiProvider;
 use std::collections::HashMap;
 use std::env;
 use std::process::{Command, Stdio};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+const USAGE: &str =
+    "supported commands: [nixv develop , nixv build , nixv build-all , nixv attach , nixv check-repro , nixv logs , nixv monitor , nixv report , nixv view , nixv baseline , nixv why , nixv drv-diff , nixv-build , nixv-shell]
+build commands accept --output-format [human , ndjson , github , gitlab] (detected in CI)
+--junit <file> to write a JUnit report of the derivations built
+--metrics-file <file> / --metrics-port <port> to export OpenMetrics
+--otlp-endpoint <url> / --otlp-file <file> to export an OpenTelemetry trace
+and --regression-threshold <percent> / --fail-on-regression to check the run against the baseline
+log-level can be set by ENV: RUST_LOG -> [ error , warn , info , debug , trace]
+to dump logs to files set ENV: DUMP_LOGS=true";
+
+/// CI logs don't render colours, nixv's output is plain there unless another format is asked.
+fn plain_output(args: &[String]) -> bool {
+    match args.iter().position(|a| a == "--output-format") {
+        Some(i) => matches!(
+            args.get(i + 1).map(|f| f.as_str()),
+            Some("github" | "gitlab")
+        ),
+        None => CiProvider::detect().is_some(),
+    }
+}
+
+/// Set on Ctrl-C so that a followed run is still finished and stored, a second one exits at once.
+fn interrupted() -> Arc<AtomicBool> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler = interrupted.clone();
+    let installed = ctrlc::set_handler(move || {
+        if handler.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+    });
+    if let Err(err) = installed {
+        log::warn!("unable to handle Ctrl-C: {}", err);
+    }
+    interrupted
+}
+
+fn exit_on_error(res: Result<(), std::io::Error>) {
+    if let Err(err) = res {
+        log::error!("{}", err);
+        std::process::exit(1);
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -26,21 +84,23 @@ fn main() {
     env_logger::builder()
         .filter_level(log_level)
         .format(|_buf, record| -> Result<(), std::io::Error> {
-            Ok({
-                log_(record);
-            })
+            log_(record);
+            Ok(())
         })
         .init();
+    if plain_output(&args) {
+        yansi::disable();
+    }
     let default = &String::from("");
     match args.split_first() {
         Some((x, xs)) => {
-            let command = x.split('/').last().unwrap_or(default);
+            let command = x.split('/').next_back().unwrap_or(default);
             match command {
                 "nixv" => {
                     let (subcommand, xargs) = xs.split_first().unwrap_or((default, &[]));
                     match subcommand.as_str() {
                         "develop" => {
-                            let _ = nix_develop_flake_process(xargs.to_vec().to_owned());
+                            exit_on_error(nix_develop_flake_process(xargs.to_vec().to_owned()));
                             let shell = "/bin/bash";
                             let nix_develop_command = format!("nix develop --command {}", shell);
                             let mut shell = Command::new("nix-shell");
@@ -54,18 +114,46 @@ fn main() {
                                 .expect("Failed to execute 'nix develop'");
                         }
                         "build" => {
-                            let _ = nix_build_flake_process(xargs.to_vec().to_owned());
+                            exit_on_error(nix_build_flake_process(xargs.to_vec().to_owned()));
+                        }
+                        "build-all" => {
+                            exit_on_error(build_all_process(xargs.to_vec().to_owned()));
+                        }
+                        "attach" => {
+                            exit_on_error(attach_process(xargs.to_vec().to_owned(), interrupted()));
+                        }
+                        "check-repro" => {
+                            exit_on_error(nix_check_repro_process(xargs.to_vec().to_owned()));
+                        }
+                        "logs" => {
+                            exit_on_error(logs_process(xargs.to_vec().to_owned()));
+                        }
+                        "monitor" => {
+                            exit_on_error(monitor_process(xargs.to_vec().to_owned()));
                         }
-                        _ => println!(
-                            "supported commands: [nixv develop , nixv build , nixv-build , nixv-shell]\nlog-level can be set by ENV: RUST_LOG -> [ error , warn , info , debug , trace]\nto dump logs to files set ENV: DUMP_LOGS=true"
-                        ),
+                        "baseline" => {
+                            exit_on_error(baseline_process(xargs.to_vec().to_owned()));
+                        }
+                        "why" => {
+                            exit_on_error(why_process(xargs.to_vec().to_owned()));
+                        }
+                        "drv-diff" => {
+                            exit_on_error(drv_diff_process(xargs.to_vec().to_owned()));
+                        }
+                        "report" => {
+                            exit_on_error(report_process(xargs.to_vec().to_owned()));
+                        }
+                        "view" => {
+                            exit_on_error(view_process(xargs.to_vec().to_owned()));
+                        }
+                        _ => println!("{}", USAGE),
                     };
                 }
                 "nixv-build" => {
-                    let _ = nix_build_process(xs.to_vec().to_owned());
+                    exit_on_error(nix_build_process(xs.to_vec().to_owned()));
                 }
                 "nixv-shell" => {
-                    let _ = nix_shell_process(xs.to_vec().to_owned());
+                    exit_on_error(nix_shell_process(xs.to_vec().to_owned()));
                     let shell = "/bin/bash";
                     let nix_develop_command = format!("nix-shell --command {}", shell);
                     let mut shell = Command::new("nix-shell");
@@ -78,13 +166,9 @@ fn main() {
                         .status()
                         .expect("Failed to execute 'nix-shell'");
                 }
-                _ => println!(
-                    "supported commands: [nixv develop , nixv build , nixv-build , nixv-shell]\nlog-level can be set by ENV: RUST_LOG -> [ error , warn , info , debug , trace]\nto dump logs to files set ENV: DUMP_LOGS=true"
-                ),
+                _ => println!("{}", USAGE),
             }
         }
-        None => println!(
-            "supported commands: [nixv develop , nixv build , nixv-build , nixv-shell]\nlog-level can be set by ENV: RUST_LOG -> [ error , warn , info , debug , trace]\nto dump logs to files set ENV: DUMP_LOGS=true"
-        ),
+        None => println!("{}", USAGE),
     }
 }
@@ -0,0 +1,186 @@
+use std::{
+    io::Error,
+    mem,
+    pin::Pin,
+    process::{ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    sync::mpsc::{channel, Sender},
+    task::JoinHandle,
+};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+
+use crate::nix_tracker::{
+    events::{run_finished, Event},
+    tracker::Tracker,
+    types::CommandState,
+};
+
+/// Events held for a consumer that falls behind, the log of nix is read no further until it
+/// catches up.
+const EVENTS_BUFFER: usize = 1024;
+
+/// A nix command followed on the tokio runtime, a [`Stream`] of its events.
+///
+/// The stream ends once nix exits, with [`Event::RunFinished`] as its last item.
+/// Any number of them can run side by side in one process. Dropping it kills nix.
+pub struct TrackedRun {
+    events: ReceiverStream<Event>,
+    task: JoinHandle<Result<(CommandState, ExitStatus), Error>>,
+}
+
+async fn track(
+    mut cmd: Command,
+    sender: Sender<Event>,
+) -> Result<(CommandState, ExitStatus), Error> {
+    // nix is killed when reading its log fails or the run is dropped, which aborts this task
+    let mut child = cmd.stderr(Stdio::piped()).kill_on_drop(true).spawn()?;
+    let mut tracker = Tracker::new();
+    // subscribers can't wait, the events are sent once the line is processed
+    let pending: Arc<Mutex<Vec<Event>>> = Arc::default();
+    let queue = pending.clone();
+    tracker.subscribe(move |event| {
+        if let Ok(mut queue) = queue.lock() {
+            queue.push(event.clone());
+        }
+    });
+    if let Some(stderr) = child.stderr.take() {
+        let mut reader = BufReader::new(stderr);
+        let mut buf: Vec<u8> = Vec::new();
+        while reader.read_until(b'\n', &mut buf).await? > 0 {
+            tracker.feed_line(&String::from_utf8_lossy(&buf));
+            buf.clear();
+            let events = match pending.lock() {
+                Ok(mut queue) => mem::take(&mut *queue),
+                Err(_) => Vec::new(),
+            };
+            for event in events {
+                // nobody listening is fine, the state is still kept
+                let _ = sender.send(event).await;
+            }
+        }
+    }
+    let state = tracker.finish();
+    let status = child.wait().await?;
+    let _ = sender.send(run_finished(&state, &status)).await;
+    Ok((state, status))
+}
+
+/// Spawns `cmd`, which has to log with `--log-format internal-json`, on the current
+/// tokio runtime.
+pub fn run_tracked_async(cmd: Command) -> TrackedRun {
+    let (sender, receiver) = channel(EVENTS_BUFFER);
+    TrackedRun {
+        events: ReceiverStream::new(receiver),
+        task: tokio::spawn(track(cmd, sender)),
+    }
+}
+
+impl TrackedRun {
+    /// Waits for nix to exit, the events not read yet are dropped.
+    pub async fn wait(mut self) -> Result<(CommandState, ExitStatus), Error> {
+        self.events.close();
+        (&mut self.task).await.map_err(Error::other)?
+    }
+}
+
+impl Drop for TrackedRun {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Stream for TrackedRun {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        Pin::new(&mut self.events).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread, time::Duration};
+
+    use tokio::runtime::Builder;
+    use tokio_stream::{StreamExt, StreamMap};
+
+    use super::*;
+
+    /// A shell pretending to be nix, building `name` and exiting with `code`.
+    fn fake_build(name: &str, code: i32) -> Command {
+        let drv = format!("/nix/store/8bj9zs3ynqxwkdkfsb9ciw6ww1ny4ndk-{name}.drv");
+        let script = format!(
+            r#"printf '%s\n' '@nix {{"action":"start","id":1,"level":3,"parent":0,"text":"building","type":105,"fields":["{drv}","",1,1]}}' '@nix {{"action":"stop","id":1}}' >&2; exit {code}"#
+        );
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(script);
+        cmd
+    }
+
+    #[test]
+    fn supervises_several_runs_at_once() {
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let mut runs = StreamMap::new();
+            runs.insert("hello", run_tracked_async(fake_build("hello-2.12.1", 0)));
+            runs.insert("broken", run_tracked_async(fake_build("broken-1.0", 1)));
+            let mut started = Vec::new();
+            let mut finished = Vec::new();
+            while let Some((name, event)) = runs.next().await {
+                match event {
+                    Event::ActivityStarted { store_path, .. } => {
+                        let store_path = store_path.unwrap_or_default();
+                        assert!(store_path.contains(name), "{store_path} in the {name} run");
+                        started.push(name);
+                    }
+                    Event::RunFinished { success, .. } => finished.push((name, success)),
+                    _ => {}
+                }
+            }
+            started.sort();
+            finished.sort();
+            assert_eq!(started, ["broken", "hello"]);
+            assert_eq!(finished, [("broken", false), ("hello", true)]);
+        });
+    }
+
+    #[test]
+    fn dropping_a_run_kills_nix() {
+        let dir = std::env::temp_dir().join(format!("nixv-async-drop-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let pid_file = dir.join("pid");
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        let alive = runtime.block_on(async {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c")
+                .arg(format!("echo $$ > {}; exec sleep 30", pid_file.display()));
+            let run = run_tracked_async(cmd);
+            let pid = loop {
+                tokio::task::yield_now().await;
+                match fs::read_to_string(&pid_file) {
+                    Ok(pid) if pid.ends_with('\n') => break pid.trim().to_owned(),
+                    _ => thread::sleep(Duration::from_millis(10)),
+                }
+            };
+            drop(run);
+            let stat = format!("/proc/{pid}/stat");
+            for _ in 0..100 {
+                // lets the runtime cancel the task
+                tokio::task::yield_now().await;
+                if fs::read_to_string(&stat).map_or(true, |stat| stat.contains(") Z ")) {
+                    return None;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            Some(pid)
+        });
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(alive, None, "sleep outlived its run");
+    }
+}
@@ -0,0 +1,254 @@
+use crate::nix_commands::options::RunOptions;
+use crate::nix_commands::runner::{check_regressions, check_status, finish_run, LiveRun};
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, Error, ErrorKind, Seek, SeekFrom},
+    os::unix::{fs::MetadataExt, process::ExitStatusExt},
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const USAGE: &str = "usage: nixv attach <log file> [--follow] [build options]
+the file holds the stderr of nix run with --log-format internal-json";
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Looking for writers goes through every open file of every process.
+const WRITERS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Whether a process other than nixv has the file (device and inode) open for writing, `None`
+/// when the open files of some processes, those of other users, can't be seen.
+fn has_writer(dev: u64, ino: u64) -> Option<bool> {
+    let processes = fs::read_dir("/proc").ok()?;
+    let mut unknown = false;
+    for process in processes.flatten() {
+        let fds = match fs::read_dir(process.path().join("fd")) {
+            Ok(fds) => fds,
+            // gone since, or not ours to look at
+            Err(err) => {
+                unknown |= err.kind() == ErrorKind::PermissionDenied;
+                continue;
+            }
+        };
+        for fd in fds.flatten() {
+            let target = match fs::metadata(fd.path()) {
+                Ok(target) => target,
+                Err(_) => continue,
+            };
+            if target.dev() != dev || target.ino() != ino {
+                continue;
+            }
+            let fdinfo = process.path().join("fdinfo").join(fd.file_name());
+            let flags = fs::read_to_string(fdinfo)
+                .ok()
+                .and_then(|info| {
+                    info.lines()
+                        .find_map(|l| l.strip_prefix("flags:"))
+                        .and_then(|f| u32::from_str_radix(f.trim(), 8).ok())
+                })
+                .unwrap_or_default();
+            // O_WRONLY or O_RDWR
+            if flags & 3 != 0 {
+                return Some(true);
+            }
+        }
+    }
+    match unknown {
+        true => None,
+        false => Some(false),
+    }
+}
+
+/// A log file read like `tail -f`: reopened when rotated, read again from the start when truncated.
+struct FollowedFile {
+    path: PathBuf,
+    reader: BufReader<File>,
+    dev: u64,
+    ino: u64,
+    position: u64,
+}
+
+impl FollowedFile {
+    fn open(path: &Path) -> Result<FollowedFile, Error> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        Ok(FollowedFile {
+            path: path.to_path_buf(),
+            reader: BufReader::new(file),
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            position: 0,
+        })
+    }
+
+    /// Appends what is available to `buf`, up to the end of the next line.
+    fn read_line(&mut self, buf: &mut Vec<u8>) -> Result<usize, Error> {
+        let n = self.reader.read_until(b'\n', buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+
+    /// Called at the end of the file, true when there is something new to read.
+    fn check(&mut self, buf: &mut Vec<u8>) -> Result<bool, Error> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            // moved away and not recreated yet
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        if metadata.dev() != self.dev || metadata.ino() != self.ino {
+            log::warn!(
+                "{} was rotated, following the new file",
+                self.path.display()
+            );
+            *self = FollowedFile::open(&self.path)?;
+            buf.clear();
+            return Ok(true);
+        }
+        if metadata.len() < self.position {
+            log::warn!("{} was truncated, reading it again", self.path.display());
+            self.reader.seek(SeekFrom::Start(0))?;
+            self.position = 0;
+            buf.clear();
+            return Ok(true);
+        }
+        Ok(metadata.len() > self.position)
+    }
+}
+
+/// The log doesn't carry the exit code of nix, a run with errors is taken as failed.
+fn inferred_status(live_run: &LiveRun) -> ExitStatus {
+    let state = live_run.state();
+    match state.errors.is_empty() {
+        true => ExitStatus::from_raw(0),
+        false => ExitStatus::from_raw(1 << 8),
+    }
+}
+
+/// Follows the log until nix is done with it, or until `interrupted` is set, the run is then
+/// finished and stored all the same.
+pub fn attach_process(args: Vec<String>, interrupted: Arc<AtomicBool>) -> Result<(), Error> {
+    let (options, args) = RunOptions::from_args(args)?;
+    let mut follow = false;
+    let mut file = None;
+    for arg in args {
+        match arg.as_str() {
+            "--follow" | "-f" => follow = true,
+            _ if file.is_none() && !arg.starts_with('-') => file = Some(PathBuf::from(arg)),
+            _ => return Err(Error::new(ErrorKind::InvalidInput, USAGE)),
+        }
+    }
+    let path = file.ok_or(Error::new(ErrorKind::InvalidInput, USAGE))?;
+    let mut followed = FollowedFile::open(&path)?;
+    let mut live_run = LiveRun::new(&options)?;
+    let mut buf: Vec<u8> = Vec::new();
+    let mut last_writers_check: Option<Instant> = None;
+    let mut writers_unknown = false;
+    while !interrupted.load(Ordering::SeqCst) {
+        followed.read_line(&mut buf)?;
+        if buf.ends_with(b"\n") {
+            live_run.line(&String::from_utf8_lossy(&buf));
+            buf.clear();
+            continue;
+        }
+        // at the end of the file, maybe in the middle of a line
+        if followed.check(&mut buf)? {
+            continue;
+        }
+        let writers_due = match last_writers_check {
+            Some(last) => last.elapsed() >= WRITERS_INTERVAL,
+            None => true,
+        };
+        if !follow && !writers_unknown && writers_due {
+            last_writers_check = Some(Instant::now());
+            match has_writer(followed.dev, followed.ino) {
+                Some(true) => {}
+                Some(false) => {
+                    // the last bytes may have landed since the end of the file was reached
+                    if followed.check(&mut buf)? {
+                        continue;
+                    }
+                    break;
+                }
+                None => {
+                    writers_unknown = true;
+                    log::warn!(
+                        "unable to see the open files of every process, following {} until Ctrl-C",
+                        path.display()
+                    );
+                }
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    if !buf.is_empty() {
+        live_run.line(&String::from_utf8_lossy(&buf));
+    }
+    let status = inferred_status(&live_run);
+    let state = live_run.finish(&status, &format!("nixv attach {}", path.display()));
+    let (_, regressions) = finish_run(state, &options);
+    check_status(status)?;
+    check_regressions(&regressions, &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, io::Write, process};
+
+    fn temp_log(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("nixv-attach-{}-{}.log", name, process::id()))
+    }
+
+    fn read_all(followed: &mut FollowedFile) -> String {
+        let mut buf = Vec::new();
+        while followed.read_line(&mut buf).unwrap() > 0 {}
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn check_reads_a_truncated_file_again() {
+        let path = temp_log("truncated");
+        fs::write(&path, "first\nsecond\n").unwrap();
+        let mut followed = FollowedFile::open(&path).unwrap();
+        assert_eq!(read_all(&mut followed), "first\nsecond\n");
+        let mut buf = Vec::new();
+        assert!(!followed.check(&mut buf).unwrap());
+        fs::write(&path, "new\n").unwrap();
+        buf.extend_from_slice(b"partial");
+        assert!(followed.check(&mut buf).unwrap());
+        assert!(buf.is_empty());
+        assert_eq!(followed.position, 0);
+        assert_eq!(read_all(&mut followed), "new\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn check_reopens_a_rotated_file() {
+        let path = temp_log("rotated");
+        let rotated = path.with_extension("log.1");
+        fs::write(&path, "old\n").unwrap();
+        let mut followed = FollowedFile::open(&path).unwrap();
+        assert_eq!(read_all(&mut followed), "old\n");
+        let mut buf = Vec::new();
+        fs::rename(&path, &rotated).unwrap();
+        // moved away and not recreated yet
+        assert!(!followed.check(&mut buf).unwrap());
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"rotated\nlonger than before\n").unwrap();
+        let ino = followed.ino;
+        assert!(followed.check(&mut buf).unwrap());
+        assert_ne!(followed.ino, ino);
+        assert_eq!(read_all(&mut followed), "rotated\nlonger than before\n");
+        // appending to the new file is picked up like any growth
+        file.write_all(b"more\n").unwrap();
+        assert!(followed.check(&mut buf).unwrap());
+        assert_eq!(read_all(&mut followed), "more\n");
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rotated).unwrap();
+    }
+}
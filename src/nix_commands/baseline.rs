@@ -0,0 +1,98 @@
+use crate::nix_history::{
+    baseline::{clear_baseline, load_baseline, set_baseline},
+    runs::{list_runs, load_run, run_id},
+};
+use crate::nix_tracker::regression::{compare, print_regressions, DEFAULT_THRESHOLD_PERCENT};
+use std::io::{Error, ErrorKind};
+
+const BASELINE_USAGE: &str = "usage: nixv baseline [set <run> , show , clear , compare [<run>] [--regression-threshold <percent>] [--fail-on-regression]]
+runs are compared with the baseline when they end";
+
+fn usage() -> Error {
+    Error::new(ErrorKind::InvalidInput, BASELINE_USAGE)
+}
+
+fn latest_run() -> Result<String, Error> {
+    match list_runs().first() {
+        Some(latest) => Ok(run_id(latest)),
+        None => Err(Error::new(ErrorKind::NotFound, "no runs recorded yet")),
+    }
+}
+
+fn compare_process(args: Vec<String>) -> Result<(), Error> {
+    let mut run = None;
+    let mut threshold = DEFAULT_THRESHOLD_PERCENT;
+    let mut fail = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fail-on-regression" => fail = true,
+            "--regression-threshold" => {
+                threshold = args
+                    .next()
+                    .and_then(|p| p.trim_end_matches('%').parse().ok())
+                    .ok_or_else(usage)?
+            }
+            _ if run.is_none() && !arg.starts_with('-') => run = Some(arg),
+            _ => return Err(usage()),
+        }
+    }
+    let baseline = load_baseline().ok_or(Error::new(
+        ErrorKind::NotFound,
+        "no baseline set, see nixv baseline set <run>",
+    ))?;
+    let run = match run {
+        Some(run) => run,
+        None => latest_run()?,
+    };
+    let (_, state) = load_run(&run).ok_or(Error::new(
+        ErrorKind::NotFound,
+        format!("no run found for {}", run),
+    ))?;
+    let regressions = compare(&baseline.state, &state, threshold);
+    print_regressions(&baseline.run, &regressions);
+    match fail && !regressions.is_empty() {
+        true => Err(Error::other(format!(
+            "{} regression(s) against the baseline",
+            regressions.len()
+        ))),
+        false => Ok(()),
+    }
+}
+
+pub fn baseline_process(args: Vec<String>) -> Result<(), Error> {
+    let (subcommand, rest) = match args.split_first() {
+        Some((subcommand, rest)) => (subcommand.as_str(), rest.to_vec()),
+        None => ("show", Vec::new()),
+    };
+    match subcommand {
+        "set" => {
+            let run = match rest.as_slice() {
+                [run] => run.to_owned(),
+                _ => return Err(usage()),
+            };
+            let (run_dir, state) = load_run(&run).ok_or(Error::new(
+                ErrorKind::NotFound,
+                format!("no run found for {}", run),
+            ))?;
+            let file = set_baseline(&run_id(&run_dir), state)?;
+            log::info!("baseline set to {} in {}", run_id(&run_dir), file.display());
+            Ok(())
+        }
+        "show" => {
+            match load_baseline() {
+                Some(baseline) => println!(
+                    "{} ({} builds, {} substitutions)",
+                    baseline.run,
+                    baseline.state.act_build.len(),
+                    baseline.state.act_substitute.len()
+                ),
+                None => println!("no baseline set"),
+            }
+            Ok(())
+        }
+        "clear" => clear_baseline(),
+        "compare" => compare_process(rest),
+        _ => Err(usage()),
+    }
+}
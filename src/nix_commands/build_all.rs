@@ -0,0 +1,273 @@
+use crate::nix_commands::runner::run_tracked;
+use crate::nix_commands::{options::OutputFormat, options::RunOptions};
+use crate::nix_history::runs::write_run_state;
+use crate::nix_logs::helpers::set_log_prefix;
+use crate::nix_tracker::{
+    build_all::{BuildAllReport, TargetRun},
+    types::CommandState,
+};
+use serde_json::Value;
+use std::{
+    io::{Error, ErrorKind},
+    process as PC,
+    sync::Mutex,
+    thread,
+    time::SystemTime,
+};
+
+const DEFAULT_JOBS: usize = 4;
+const USAGE: &str = "usage: nixv build-all [-j <jobs>] [--otlp-endpoint <url>] <installable>... [-- <nix build args>]
+an installable like .#checks.* or .#packages.<system>.* builds every one the flake outputs";
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn nix_command() -> PC::Command {
+    let mut cmd = PC::Command::new("nix");
+    cmd.arg("--extra-experimental-features")
+        .arg("flakes")
+        .arg("--extra-experimental-features")
+        .arg("nix-command");
+    cmd
+}
+
+fn nix_output(cmd: &mut PC::Command) -> Result<Vec<u8>, Error> {
+    let output = cmd.output()?;
+    match output.status.success() {
+        true => Ok(output.stdout),
+        false => Err(Error::other(format!(
+            "{:?} failed: {}",
+            cmd,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
+
+fn current_system() -> Result<String, Error> {
+    let stdout = nix_output(nix_command().args([
+        "eval",
+        "--impure",
+        "--raw",
+        "--expr",
+        "builtins.currentSystem",
+    ]))?;
+    Ok(String::from_utf8_lossy(&stdout).trim().to_owned())
+}
+
+/// The names under `path` in the output of `nix flake show --json`.
+fn attribute_names(show: &Value, path: &[&str]) -> Vec<String> {
+    match path.iter().try_fold(show, |value, name| value.get(name)) {
+        Some(Value::Object(attributes)) => attributes.keys().cloned().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Expands `<flake>#<output>.*` or `<flake>#<output>.<system>[.<attribute path>].*` with
+/// `nix flake show`, other installables are kept as they are.
+fn expand(installable: &str, system: &mut Option<String>) -> Result<Vec<String>, Error> {
+    let (flake, attr) = match installable
+        .strip_suffix(".*")
+        .and_then(|pattern| pattern.split_once('#'))
+    {
+        Some(split) => split,
+        None => return Ok(vec![installable.to_owned()]),
+    };
+    let mut path: Vec<String> = attr.split('.').map(String::from).collect();
+    if path.len() == 1 {
+        if system.is_none() {
+            *system = Some(current_system()?);
+        }
+        path.push(system.clone().unwrap_or_default());
+    }
+    let stdout = nix_output(nix_command().args(["flake", "show", "--json", flake]))?;
+    let show: Value = serde_json::from_slice(&stdout)?;
+    let path: Vec<&str> = path.iter().map(String::as_str).collect();
+    let names = attribute_names(&show, &path);
+    let prefix = path.join(".");
+    if names.is_empty() {
+        log::warn!("{} has no {}", flake, prefix);
+    }
+    Ok(names
+        .iter()
+        .map(|name| format!("{}#{}.{}", flake, prefix, name))
+        .collect())
+}
+
+/// The options each target is run with. Reports written to one file or port and the ndjson
+/// events, whose activity ids collide between targets, can't take several concurrent runs.
+fn target_options(options: RunOptions) -> Result<RunOptions, Error> {
+    let unsupported = [
+        (
+            options.output_format == OutputFormat::Ndjson,
+            "--output-format ndjson",
+        ),
+        (options.junit.is_some(), "--junit"),
+        (options.metrics_file.is_some(), "--metrics-file"),
+        (options.metrics_port.is_some(), "--metrics-port"),
+        (options.otlp_file.is_some(), "--otlp-file"),
+        (options.fail_on_regression, "--fail-on-regression"),
+        (
+            options.regression_threshold.is_some(),
+            "--regression-threshold",
+        ),
+    ];
+    if let Some((_, flag)) = unsupported.iter().find(|(set, _)| *set) {
+        return Err(invalid(format!(
+            "{} can't be used with build-all, every target is a run of its own\n{}",
+            flag, USAGE
+        )));
+    }
+    // logs of concurrent builds can't be grouped, so plain output whatever the CI
+    Ok(RunOptions {
+        output_format: OutputFormat::Human,
+        ..options
+    })
+}
+
+fn build_target(installable: &str, nix_args: &[String], options: &RunOptions) -> TargetRun {
+    set_log_prefix(installable);
+    let start = SystemTime::now();
+    let mut binding = PC::Command::new("nix");
+    let cmd = binding
+        .arg("build")
+        .arg("-v")
+        .arg("--log-format")
+        .arg("internal-json")
+        .arg("--extra-experimental-features")
+        .arg("flakes")
+        .arg("--extra-experimental-features")
+        .arg("nix-command")
+        // concurrent builds would all replace ./result
+        .arg("--no-link")
+        .arg(installable)
+        .args(nix_args);
+    match run_tracked(cmd, options) {
+        Ok((state, status)) => {
+            let run_dir = state.run_dir.clone();
+            let json_state = CommandState::to_json(state);
+            if let Some(run_dir) = &run_dir {
+                if let Err(err) = write_run_state(run_dir, &json_state) {
+                    log::warn!("unable to store the run in {}: {}", run_dir.display(), err);
+                }
+            }
+            match status.success() {
+                true => log::info!("done"),
+                false => log::error!("failed: {}", status),
+            }
+            TargetRun::new(installable, &json_state, &status, run_dir)
+        }
+        Err(err) => {
+            log::error!("unable to run nix: {}", err);
+            TargetRun::not_run(installable, start, &err)
+        }
+    }
+}
+
+pub fn build_all_process(args: Vec<String>) -> Result<(), Error> {
+    let mut jobs = DEFAULT_JOBS;
+    let mut patterns = Vec::new();
+    let (args, nix_args) = match args.iter().position(|arg| arg == "--") {
+        Some(i) => (args[..i].to_vec(), args[i + 1..].to_vec()),
+        None => (args, Vec::new()),
+    };
+    let (options, args) = RunOptions::from_args(args)?;
+    let options = target_options(options)?;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-j" | "--jobs" => {
+                let value = args.next().unwrap_or_default();
+                jobs = match value.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(invalid(format!("{} expects a number, got {}", arg, value))),
+                };
+            }
+            _ if arg.starts_with('-') => {
+                return Err(invalid(format!(
+                    "unknown option {}, nix options go after --\n{}",
+                    arg, USAGE
+                )))
+            }
+            _ => patterns.push(arg),
+        }
+    }
+    if patterns.is_empty() {
+        return Err(invalid(USAGE.to_owned()));
+    }
+    let mut system = None;
+    let mut installables: Vec<String> = Vec::new();
+    for pattern in patterns.iter() {
+        for installable in expand(pattern, &mut system)? {
+            if !installables.contains(&installable) {
+                installables.push(installable);
+            }
+        }
+    }
+    if installables.is_empty() {
+        return Err(invalid(String::from("nothing to build")));
+    }
+    let jobs = jobs.min(installables.len());
+    log::info!(
+        "building {} targets, {} at a time",
+        installables.len(),
+        jobs
+    );
+    let start = SystemTime::now();
+    let next = Mutex::new(0);
+    let results: Mutex<Vec<Option<TargetRun>>> = Mutex::new(vec![None; installables.len()]);
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let i = {
+                    let mut next = next.lock().unwrap();
+                    *next += 1;
+                    *next - 1
+                };
+                let installable = match installables.get(i) {
+                    Some(installable) => installable,
+                    None => break,
+                };
+                let target = build_target(installable, &nix_args, &options);
+                results.lock().unwrap()[i] = Some(target);
+            });
+        }
+    });
+    let targets = results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect();
+    let report = BuildAllReport::new(start, jobs, targets);
+    report.print();
+    report.dump_to_file("build_all.json");
+    match report.success() {
+        true => Ok(()),
+        false => Err(Error::other("Some targets failed to build")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attribute_names_follow_the_whole_path() {
+        let show: Value = serde_json::from_str(
+            r#"{"packages":{"x86_64-linux":{
+                "hello":{"type":"derivation","name":"hello-2.12.1"},
+                "tools":{"fmt":{"type":"derivation"},"lint":{"type":"derivation"}}
+            }}}"#,
+        )
+        .unwrap();
+        let mut names = attribute_names(&show, &["packages", "x86_64-linux"]);
+        names.sort();
+        assert_eq!(names, ["hello", "tools"]);
+        let mut names = attribute_names(&show, &["packages", "x86_64-linux", "tools"]);
+        names.sort();
+        assert_eq!(names, ["fmt", "lint"]);
+        assert!(attribute_names(&show, &["packages", "aarch64-linux"]).is_empty());
+        assert!(attribute_names(&show, &["checks", "x86_64-linux"]).is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+use crate::nix_history::runs::{list_runs, load_run, read_run_state, run_id};
+use crate::nix_tracker::{
+    drv_diff::{derivers, path_name, show_derivations, DrvDiff},
+    eta::pname,
+    types::JSONCommandState,
+};
+use std::{
+    collections::{HashSet, VecDeque},
+    io::{Error, ErrorKind},
+};
+
+const DRV_DIFF_USAGE: &str =
+    "usage: nixv drv-diff <pkg-or-drv> [<old-run> [<new-run>]] [--shallow] , nixv drv-diff <old.drv> <new.drv> [--shallow]
+compares the derivations of a package in two runs, by default the latest one and the one before with another derivation";
+
+struct DrvDiffArgs {
+    query: String,
+    runs: Vec<String>,
+    shallow: bool,
+}
+
+fn parse_args(args: Vec<String>) -> Result<DrvDiffArgs, Error> {
+    let usage = || Error::new(ErrorKind::InvalidInput, DRV_DIFF_USAGE);
+    let mut positional = Vec::new();
+    let mut shallow = false;
+    for arg in args {
+        match arg.as_str() {
+            "--shallow" => shallow = true,
+            _ if !arg.starts_with('-') && positional.len() < 3 => positional.push(arg),
+            _ => return Err(usage()),
+        }
+    }
+    let mut positional = positional.into_iter();
+    Ok(DrvDiffArgs {
+        query: positional.next().ok_or_else(usage)?,
+        runs: positional.collect(),
+        shallow,
+    })
+}
+
+/// The derivation of a package in a run, from the paths it required: derivations it built and
+/// the derivers of the paths it substituted, matched by path, name and version or package name.
+fn run_derivation(state: &JSONCommandState, query: &str) -> Option<String> {
+    let mut required: Vec<&String> = state.required_derivations.iter().collect();
+    required.sort();
+    let keys: [fn(&str) -> &str; 3] = [|p| p, path_name, |p| pname(path_name(p))];
+    let matching: Vec<String> = keys
+        .into_iter()
+        .map(|key| {
+            required
+                .iter()
+                .filter(|p| key(p) == query)
+                .map(|p| p.to_string())
+                .collect::<Vec<String>>()
+        })
+        .find(|matching| !matching.is_empty())?;
+    let (mut drvs, outputs): (Vec<String>, Vec<String>) =
+        matching.into_iter().partition(|p| p.ends_with(".drv"));
+    if !outputs.is_empty() {
+        if let Ok(derivers) = derivers(&outputs) {
+            drvs.extend(outputs.iter().filter_map(|o| derivers.get(o).cloned()));
+        }
+    }
+    drvs.sort();
+    drvs.dedup();
+    if drvs.len() > 1 {
+        log::warn!(
+            "{} matches several derivations, using {}: {}",
+            query,
+            drvs[0],
+            drvs[1..].join(" ")
+        );
+    }
+    drvs.into_iter().next()
+}
+
+/// A run id and the derivation of the package in it.
+type RunDerivation = (String, String);
+
+fn load_run_derivation(run: &str, query: &str) -> Result<RunDerivation, Error> {
+    let (run_dir, state) = load_run(run).ok_or(Error::new(
+        ErrorKind::NotFound,
+        format!("no run found for {}", run),
+    ))?;
+    match run_derivation(&state, query) {
+        Some(drv) => Ok((run_id(&run_dir), drv)),
+        None => Err(Error::new(
+            ErrorKind::NotFound,
+            format!("run {} has no derivation for {}", run_id(&run_dir), query),
+        )),
+    }
+}
+
+/// The derivations to compare with the runs they come from, given as runs or looked up in the
+/// history, newest first.
+fn select(args: &DrvDiffArgs) -> Result<(RunDerivation, RunDerivation), Error> {
+    let not_found = |msg: String| Error::new(ErrorKind::NotFound, msg);
+    let history = || {
+        list_runs().into_iter().filter_map(|run_dir| {
+            let drv = run_derivation(&read_run_state(&run_dir)?, &args.query)?;
+            Some((run_id(&run_dir), drv))
+        })
+    };
+    let new = match args.runs.get(1) {
+        Some(run) => load_run_derivation(run, &args.query)?,
+        None if args.runs.is_empty() => history()
+            .next()
+            .ok_or_else(|| not_found(format!("no run has a derivation for {}", args.query)))?,
+        // the new run is the latest one when only the old run is given
+        None => {
+            let latest = list_runs()
+                .first()
+                .map(|r| run_id(r))
+                .ok_or_else(|| not_found("no runs recorded yet".to_owned()))?;
+            load_run_derivation(&latest, &args.query)?
+        }
+    };
+    let old = match args.runs.first() {
+        Some(run) => load_run_derivation(run, &args.query)?,
+        None => history()
+            .find(|(run, drv)| *run < new.0 && *drv != new.1)
+            .ok_or_else(|| {
+                not_found(format!(
+                    "no run before {} has another derivation for {}",
+                    new.0, args.query
+                ))
+            })?,
+    };
+    Ok((old, new))
+}
+
+pub fn drv_diff_process(args: Vec<String>) -> Result<(), Error> {
+    let args = parse_args(args)?;
+    let (old, new) = match args.runs.as_slice() {
+        [new] if args.query.ends_with(".drv") && new.ends_with(".drv") => {
+            (args.query.to_owned(), new.to_owned())
+        }
+        _ => {
+            let ((old_run, old), (new_run, new)) = select(&args)?;
+            log::info!(
+                "comparing {} of run {} with run {}",
+                path_name(&new),
+                old_run,
+                new_run
+            );
+            (old, new)
+        }
+    };
+    if old == new {
+        println!("both runs have the same derivation {}", new);
+        return Ok(());
+    }
+    let drvs = show_derivations(&[old.clone(), new.clone()], true)?;
+    // like nix-diff, the inputs that changed are compared too unless --shallow is given
+    let mut queue = VecDeque::from([(old, new)]);
+    let mut seen = HashSet::new();
+    let mut first = true;
+    while let Some((old, new)) = queue.pop_front() {
+        let (old, new) = match (drvs.get(&old), drvs.get(&new)) {
+            (Some(old), Some(new)) => (old, new),
+            _ => continue,
+        };
+        let diff = DrvDiff::compare(old, new, &drvs);
+        if !first {
+            println!();
+        }
+        first = false;
+        diff.print();
+        if args.shallow {
+            break;
+        }
+        for (old, new) in diff.changed_inputs() {
+            if seen.insert(new.to_owned()) {
+                queue.push_back((old.to_owned(), new.to_owned()));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use crate::nix_history::{
+    log_store::{find_entries, log_path, read_index, LogIndexEntry},
+    runs::{find_run, list_runs, run_id},
+};
+use regex::Regex;
+use std::{
+    fs,
+    io::{BufRead, BufReader, Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+const LOGS_USAGE: &str = "usage: nixv logs [<pkg-or-drv>] [--run <run>] [--grep <regex>]";
+
+struct LogsArgs {
+    query: Option<String>,
+    run: Option<String>,
+    grep: Option<String>,
+}
+
+fn parse_args(args: Vec<String>) -> Result<LogsArgs, Error> {
+    let usage = || Error::new(ErrorKind::InvalidInput, LOGS_USAGE);
+    let mut parsed = LogsArgs {
+        query: None,
+        run: None,
+        grep: None,
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--run" => parsed.run = Some(args.next().ok_or_else(usage)?),
+            "--grep" => parsed.grep = Some(args.next().ok_or_else(usage)?),
+            _ if parsed.query.is_none() && !arg.starts_with("--") => parsed.query = Some(arg),
+            _ => return Err(usage()),
+        }
+    }
+    Ok(parsed)
+}
+
+fn selected_runs(run: &Option<String>) -> Result<Vec<PathBuf>, Error> {
+    match run {
+        Some(run) => match find_run(run) {
+            Some(run_dir) => Ok(vec![run_dir]),
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no run found for {}", run),
+            )),
+        },
+        None => Ok(list_runs()),
+    }
+}
+
+fn print_runs() {
+    let runs = list_runs();
+    if runs.is_empty() {
+        println!("no runs recorded yet");
+    }
+    for run_dir in runs {
+        println!(
+            "{} ({} build logs)",
+            run_id(&run_dir),
+            read_index(&run_dir).len()
+        );
+    }
+}
+
+fn print_log(run_dir: &Path, entry: &LogIndexEntry) -> Result<(), Error> {
+    println!(
+        "==> {} ({}) from run {}",
+        entry.package_name,
+        entry.drv_path,
+        run_id(run_dir)
+    );
+    print!("{}", fs::read_to_string(log_path(run_dir, entry))?);
+    Ok(())
+}
+
+fn grep_logs(runs: Vec<PathBuf>, query: &Option<String>, pattern: &str) -> Result<(), Error> {
+    let re = Regex::new(pattern).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let mut matches = 0;
+    for run_dir in runs {
+        let index = read_index(&run_dir);
+        let entries = match query {
+            Some(query) => find_entries(&index, query),
+            None => index.iter().collect(),
+        };
+        for entry in entries {
+            let file = match fs::File::open(log_path(&run_dir, entry)) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            for (n, line) in BufReader::new(file)
+                .lines()
+                .map_while(Result::ok)
+                .enumerate()
+            {
+                if re.is_match(&line) {
+                    matches += 1;
+                    println!(
+                        "{} {}:{}: {}",
+                        run_id(&run_dir),
+                        entry.package_name,
+                        n + 1,
+                        line
+                    );
+                }
+            }
+        }
+    }
+    if matches == 0 {
+        log::warn!("no log line matches {}", pattern);
+    }
+    Ok(())
+}
+
+pub fn logs_process(args: Vec<String>) -> Result<(), Error> {
+    let args = parse_args(args)?;
+    let runs = selected_runs(&args.run)?;
+    match (&args.query, &args.grep) {
+        (_, Some(pattern)) => grep_logs(runs, &args.query, pattern),
+        (Some(query), None) => {
+            // the newest run that has a log for the package
+            for run_dir in runs {
+                let index = read_index(&run_dir);
+                let entries = find_entries(&index, query);
+                if !entries.is_empty() {
+                    for entry in entries {
+                        print_log(&run_dir, entry)?;
+                    }
+                    return Ok(());
+                }
+            }
+            Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no build log found for {}", query),
+            ))
+        }
+        (None, None) => {
+            print_runs();
+            Ok(())
+        }
+    }
+}
@@ -1,4 +1,18 @@
+#[cfg(feature = "async")]
+pub mod async_runner;
+pub mod attach;
+pub mod baseline;
+pub mod build_all;
+pub mod drv_diff;
+pub mod logs;
+pub mod monitor;
 pub mod nix_build;
 pub mod nix_build_flake;
+pub mod nix_check_repro;
 pub mod nix_develop_flake;
 pub mod nix_shell;
+pub mod options;
+pub mod report;
+pub mod runner;
+pub mod view;
+pub mod why;
@@ -0,0 +1,144 @@
+use crate::nix_tracker::monitor::MachineSnapshot;
+use crossterm::{
+    cursor::MoveTo,
+    execute,
+    terminal::{Clear, ClearType},
+};
+use std::{
+    fmt::Write as _,
+    io::{self, Error, ErrorKind, IsTerminal, Write},
+    thread,
+    time::{Duration, SystemTime},
+};
+use yansi::Paint;
+
+const USAGE: &str = "usage: nixv monitor [--interval <seconds>] [--once] [--json]";
+
+/// Whole seconds are enough for a live view.
+fn ago(time: SystemTime, now: SystemTime) -> String {
+    format!(
+        "{}s",
+        now.duration_since(time).unwrap_or_default().as_secs()
+    )
+}
+
+fn render(snapshot: &MachineSnapshot) -> String {
+    let mut text = String::new();
+    let load = match snapshot.load {
+        Some((one, five, fifteen)) => format!("load {:.2} {:.2} {:.2}", one, five, fifteen),
+        None => String::from("load unknown"),
+    };
+    let _ = writeln!(
+        text,
+        "{} on {} cpus, {} builds running, {} nix clients connected",
+        load,
+        snapshot.cpus,
+        snapshot.running.len(),
+        snapshot.daemon_workers
+    );
+    let _ = writeln!(text);
+    match snapshot.running.is_empty() {
+        true => {
+            let _ = writeln!(text, "{}", Paint::green("no build running"));
+        }
+        false => {
+            let width = snapshot
+                .running
+                .iter()
+                .map(|b| b.name.len())
+                .max()
+                .unwrap_or(0);
+            let _ = writeln!(
+                text,
+                "{}",
+                Paint::bold(&format!(
+                    "{:width$} {:>10} {:>6} {:>6}  busiest",
+                    "running",
+                    "for",
+                    "procs",
+                    "cpu",
+                    width = width
+                ))
+            );
+            for build in snapshot.running.iter() {
+                let cpu = match build.cpu_percent {
+                    Some(percent) => format!("{:.0}%", percent),
+                    None => String::from("-"),
+                };
+                let _ = writeln!(
+                    text,
+                    "{:width$} {:>10} {:>6} {:>6}  {}",
+                    build.name,
+                    ago(build.start, snapshot.time),
+                    build.processes,
+                    cpu,
+                    build.command.as_deref().unwrap_or("-"),
+                    width = width
+                );
+            }
+        }
+    }
+    let finished = snapshot.finished();
+    if !finished.is_empty() {
+        let _ = writeln!(text);
+        let _ = writeln!(text, "{}", Paint::bold("recently finished"));
+        for log in finished {
+            let _ = writeln!(
+                text,
+                "{} ago {}",
+                ago(log.modified, snapshot.time),
+                log.name
+            );
+        }
+    }
+    text
+}
+
+pub fn monitor_process(args: Vec<String>) -> Result<(), Error> {
+    let mut interval = Duration::from_secs(2);
+    let mut once = false;
+    let mut json = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--once" => once = true,
+            "--json" => json = true,
+            "--interval" => {
+                let value = args.next().unwrap_or_default();
+                interval = match value.parse::<f64>() {
+                    Ok(secs) if secs > 0.0 => Duration::from_secs_f64(secs),
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("--interval expects a number of seconds, got {}", value),
+                        ))
+                    }
+                }
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidInput, USAGE)),
+        }
+    }
+    let redraw = !once && !json && io::stdout().is_terminal();
+    let mut previous: Option<MachineSnapshot> = None;
+    loop {
+        let snapshot = MachineSnapshot::take(previous.as_ref());
+        let mut stdout = io::stdout();
+        if json {
+            writeln!(stdout, "{}", serde_json::to_string(&snapshot)?)?;
+        } else {
+            if redraw {
+                execute!(stdout, Clear(ClearType::All), MoveTo(0, 0))?;
+            }
+            write!(stdout, "{}", render(&snapshot))?;
+            if !redraw && !once {
+                writeln!(stdout)?;
+            }
+        }
+        stdout.flush()?;
+        if once {
+            return Ok(());
+        }
+        previous = Some(snapshot);
+        thread::sleep(interval);
+    }
+}
@@ -1,47 +1,17 @@
-use crate::{
-    nix_logs::{helpers::dump_state_to_file, parser::parse, process_logs::process_log},
-    nix_tracker::types::CommandState,
-};
-use std::{
-    io::{BufRead, BufReader, Error, ErrorKind},
-    process::{self as PC, Stdio},
-    time::SystemTime,
-};
+use crate::nix_commands::options::RunOptions;
+use crate::nix_commands::runner::{check_regressions, check_status, finish_run, run_tracked};
+use std::{io::Error, process as PC};
 
 pub fn nix_build_process(args: Vec<String>) -> Result<(), Error> {
+    let (options, args) = RunOptions::from_args(args)?;
     let mut binding = PC::Command::new("nix-build");
     let cmd = binding
         .arg("-v")
         .arg("--log-format")
         .arg("internal-json")
-        .args(args)
-        .stderr(Stdio::piped())
-        .stdout(Stdio::piped());
-    let mut p = cmd.spawn().expect("unable to run the command");
-    let _stdout = p
-        .stdout
-        .as_mut()
-        .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
-
-    let stderr = p
-        .stderr
-        .as_mut()
-        .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output error."))?;
-
-    let reader = BufReader::new(stderr);
-    let mut state = CommandState::new();
-    reader.lines().for_each(|l| match l {
-        Ok(line) => {
-            let (res, id) = parse(line.clone());
-            process_log(id, res.clone(), &mut state);
-        }
-        Err(_) => {}
-    });
-    state.end = Some(SystemTime::now());
-    dump_state_to_file(state);
-    if !p.wait().expect("command wasn't running").success(){
-        log::error!("Nix build failed");
-        std::process::exit(1);
-    }
-    Ok(())
+        .args(args);
+    let (state, status) = run_tracked(cmd, &options)?;
+    let (_, regressions) = finish_run(state, &options);
+    check_status(status)?;
+    check_regressions(&regressions, &options)
 }
@@ -0,0 +1,86 @@
+use crate::nix_commands::options::RunOptions;
+use crate::nix_commands::runner::{finish_run, run_tracked};
+use crate::nix_tracker::repro::{ReproOutcome, ReproReport};
+use std::{
+    io::Error,
+    path::Path,
+    process::{self as PC, Stdio},
+};
+
+fn diffoscope_available() -> bool {
+    PC::Command::new("diffoscope")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+fn run_diffoscope(package_name: &str, output: &str, check_path: &str) -> Option<String> {
+    let diff_file = format!("repro_{}.diffoscope.txt", package_name);
+    log::info!("running diffoscope on {} and {}", output, check_path);
+    let status = PC::Command::new("diffoscope")
+        .arg("--text")
+        .arg(&diff_file)
+        .arg(output)
+        .arg(check_path)
+        .stdout(Stdio::null())
+        .status();
+    match status {
+        // diffoscope exits with 1 when differences were found
+        Ok(s) if s.code() == Some(0) || s.code() == Some(1) => Some(diff_file),
+        Ok(s) => {
+            log::warn!("diffoscope exited with {}", s);
+            None
+        }
+        Err(err) => {
+            log::warn!("unable to run diffoscope: {}", err);
+            None
+        }
+    }
+}
+
+pub fn nix_check_repro_process(args: Vec<String>) -> Result<(), Error> {
+    let (options, args) = RunOptions::from_args(args)?;
+    let mut binding = PC::Command::new("nix");
+    let cmd = binding
+        .arg("build")
+        .arg("-v")
+        .arg("--log-format")
+        .arg("internal-json")
+        .arg("--extra-experimental-features")
+        .arg("flakes")
+        .arg("--extra-experimental-features")
+        .arg("nix-command")
+        .arg("--rebuild")
+        .arg("--keep-failed")
+        .arg("--no-link")
+        .args(&args);
+    let (state, status) = run_tracked(cmd, &options)?;
+    let mut report = ReproReport::from_state(args, &state);
+    finish_run(state, &options);
+
+    let diffoscope = diffoscope_available();
+    for d in report.derivations.iter_mut() {
+        if let ReproOutcome::NotReproducible {
+            output,
+            check_path: Some(check_path),
+            diff,
+        } = &mut d.outcome
+        {
+            if diffoscope && Path::new(check_path).exists() {
+                *diff = run_diffoscope(&d.package_name, output, check_path);
+            }
+        }
+    }
+    report.print_summary();
+    report.dump_to_file("repro_report.json");
+    match report.is_reproducible() && status.success() {
+        true => Ok(()),
+        false if !report.checked_any() => Err(Error::other(
+            "Reproducibility check failed: nothing was checked",
+        )),
+        false => Err(Error::other("Reproducibility check failed")),
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    io::{Error, ErrorKind},
+    path::PathBuf,
+};
+
+use crate::nix_tracker::ci::CiProvider;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    Human,
+    Ndjson,
+    /// collapsed build logs and annotations, the default when running in CI
+    Ci(CiProvider),
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        match CiProvider::detect() {
+            Some(provider) => OutputFormat::Ci(provider),
+            None => OutputFormat::Human,
+        }
+    }
+}
+
+/// Options understood by nixv itself, everything else is handed to nix.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct RunOptions {
+    pub output_format: OutputFormat,
+    /// where to write a JUnit report of the run
+    pub junit: Option<PathBuf>,
+    /// OpenMetrics textfile kept up to date during the run
+    pub metrics_file: Option<PathBuf>,
+    /// local port serving OpenMetrics during the run
+    pub metrics_port: Option<u16>,
+    /// OTLP/HTTP collector the trace of the run is sent to
+    pub otlp_endpoint: Option<String>,
+    /// file the OTLP/JSON trace of the run is written to
+    pub otlp_file: Option<PathBuf>,
+    /// rise in build time, in percent, flagged against the baseline
+    pub regression_threshold: Option<u32>,
+    /// whether regressions against the baseline fail the command
+    pub fail_on_regression: bool,
+}
+
+fn expect_value(flag: &str, value: Option<String>) -> Result<String, Error> {
+    value.ok_or(Error::new(
+        ErrorKind::InvalidInput,
+        format!("{} expects a value", flag),
+    ))
+}
+
+fn parse_output_format(value: Option<String>) -> Result<OutputFormat, Error> {
+    match value.as_deref() {
+        Some("human") => Ok(OutputFormat::Human),
+        Some("ndjson") => Ok(OutputFormat::Ndjson),
+        Some("github") => Ok(OutputFormat::Ci(CiProvider::Github)),
+        Some("gitlab") => Ok(OutputFormat::Ci(CiProvider::Gitlab)),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--output-format expects one of [human , ndjson , github , gitlab]",
+        )),
+    }
+}
+
+impl RunOptions {
+    pub fn from_args(args: Vec<String>) -> Result<(RunOptions, Vec<String>), Error> {
+        let mut options = RunOptions::default();
+        let mut rest = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--output-format" => options.output_format = parse_output_format(args.next())?,
+                "--junit" => options.junit = Some(expect_value(&arg, args.next())?.into()),
+                "--metrics-file" => {
+                    options.metrics_file = Some(expect_value(&arg, args.next())?.into())
+                }
+                "--otlp-endpoint" => options.otlp_endpoint = Some(expect_value(&arg, args.next())?),
+                "--otlp-file" => options.otlp_file = Some(expect_value(&arg, args.next())?.into()),
+                "--fail-on-regression" => options.fail_on_regression = true,
+                "--regression-threshold" => {
+                    let percent = expect_value(&arg, args.next())?;
+                    options.regression_threshold =
+                        Some(percent.trim_end_matches('%').parse().map_err(|_| {
+                            Error::new(
+                                ErrorKind::InvalidInput,
+                                format!(
+                                    "--regression-threshold expects a percentage, got {}",
+                                    percent
+                                ),
+                            )
+                        })?)
+                }
+                "--metrics-port" => {
+                    let port = expect_value(&arg, args.next())?;
+                    options.metrics_port = Some(port.parse().map_err(|_| {
+                        Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("--metrics-port expects a port, got {}", port),
+                        )
+                    })?)
+                }
+                _ => match arg.strip_prefix("--output-format=") {
+                    Some(value) => {
+                        options.output_format = parse_output_format(Some(value.to_owned()))?
+                    }
+                    None => rest.push(arg),
+                },
+            }
+        }
+        Ok((options, rest))
+    }
+}
@@ -0,0 +1,39 @@
+use crate::nix_history::runs::{list_runs, load_run, run_id};
+use crate::nix_tracker::html_report::html_report;
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::PathBuf,
+};
+
+const REPORT_USAGE: &str = "usage: nixv report [<run-or-state.json>] [-o <report.html>]";
+
+pub fn report_process(args: Vec<String>) -> Result<(), Error> {
+    let mut run = None;
+    let mut output = PathBuf::from("report.html");
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => match args.next() {
+                Some(file) => output = PathBuf::from(file),
+                None => return Err(Error::new(ErrorKind::InvalidInput, REPORT_USAGE)),
+            },
+            _ if run.is_none() && !arg.starts_with('-') => run = Some(arg),
+            _ => return Err(Error::new(ErrorKind::InvalidInput, REPORT_USAGE)),
+        }
+    }
+    let run = match run {
+        Some(run) => run,
+        None => match list_runs().first() {
+            Some(latest) => run_id(latest),
+            None => return Err(Error::new(ErrorKind::NotFound, "no runs recorded yet")),
+        },
+    };
+    let (run_dir, state) = load_run(&run).ok_or(Error::new(
+        ErrorKind::NotFound,
+        format!("no run found for {}", run),
+    ))?;
+    fs::write(&output, html_report(&state, Some(&run_dir)))?;
+    log::info!("report written to {}", output.display());
+    Ok(())
+}
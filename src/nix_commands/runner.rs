@@ -0,0 +1,273 @@
+use super::options::{OutputFormat, RunOptions};
+use crate::{
+    nix_history::{baseline::load_baseline, log_store::LogStore, runs::create_run_dir},
+    nix_logs::{
+        helpers::{dump_state_to_file, human_output_to_stderr},
+        log_sink::LogSink,
+        parser::parse,
+    },
+    nix_tracker::{
+        ci::{print_annotations, write_step_summary, CiLog},
+        closure::ClosureReport,
+        eta::{BuildHistory, Eta},
+        eval::{enable_eval_stats, EvalReport},
+        events::{emit, run_finished},
+        junit::write_junit_report,
+        metrics::MetricsExporter,
+        otlp::{send_trace, trace, write_trace},
+        regression::{compare, print_regressions, Regression, DEFAULT_THRESHOLD_PERCENT},
+        tracker::Tracker,
+        types::{CommandState, JSONCommandState},
+    },
+};
+use std::{
+    io::{BufRead, BufReader, Error},
+    path::PathBuf,
+    process::{self as PC, ChildStdout, ExitStatus, Stdio},
+    thread,
+};
+
+/// Follows the internal-json log of one nix command with everything the options ask for:
+/// events, CI groups, metrics and the run directory with its build logs.
+pub struct LiveRun {
+    options: RunOptions,
+    ci_log: Option<CiLog>,
+    metrics: Option<MetricsExporter>,
+    eta: Eta,
+    tracker: Tracker,
+    log_sink: LogSink,
+    run_dir: Option<PathBuf>,
+    log_store: Option<LogStore>,
+}
+
+impl LiveRun {
+    pub fn new(options: &RunOptions) -> Result<LiveRun, Error> {
+        let ndjson = options.output_format == OutputFormat::Ndjson;
+        if ndjson {
+            human_output_to_stderr();
+        }
+        let ci_log = match options.output_format {
+            OutputFormat::Ci(provider) => Some(CiLog::new(provider)),
+            _ => None,
+        };
+        let metrics = match (&options.metrics_file, options.metrics_port) {
+            (None, None) => None,
+            (file, port) => Some(MetricsExporter::new(file.clone(), port)?),
+        };
+        let mut tracker = Tracker::new();
+        if ndjson {
+            tracker.subscribe(|event| emit(event.clone()));
+        }
+        let log_sink = LogSink::new();
+        let (run_dir, log_store) = match create_run_dir() {
+            Ok(run_dir) => {
+                let log_store = LogStore::new(&run_dir, log_sink.sender());
+                (Some(run_dir), Some(log_store))
+            }
+            Err(err) => {
+                log::warn!("unable to create a run directory: {}", err);
+                (None, None)
+            }
+        };
+        Ok(LiveRun {
+            options: options.clone(),
+            ci_log,
+            metrics,
+            eta: Eta::new(BuildHistory::load()),
+            tracker,
+            log_sink,
+            run_dir,
+            log_store,
+        })
+    }
+
+    pub fn line(&mut self, line: &str) {
+        let (res, id) = parse(line.trim_end());
+        self.log_sink.record(id, &res);
+        if let Some(log_store) = self.log_store.as_mut() {
+            log_store.record(id, &res, self.tracker.state());
+        }
+        let captured = match self.ci_log.as_mut() {
+            Some(ci_log) => ci_log.record(id, &res, self.tracker.state()),
+            None => false,
+        };
+        self.eta.record(id, &res, self.tracker.state());
+        if !captured {
+            self.tracker.feed(id, res);
+        }
+        if let Some(metrics) = self.metrics.as_mut() {
+            metrics.update(self.tracker.state());
+        }
+    }
+
+    pub fn state(&self) -> &CommandState {
+        self.tracker.state()
+    }
+
+    /// Flushes the logs and exports the finished run, `command` names it in the trace.
+    pub fn finish(self, status: &ExitStatus, command: &str) -> CommandState {
+        if let Some(ci_log) = self.ci_log {
+            ci_log.finish(self.tracker.state());
+        }
+        if let Some(log_store) = self.log_store {
+            log_store.finish();
+        }
+        let log_write_failures = self.log_sink.finish();
+        let mut state = self.tracker.finish();
+        state.run_dir = self.run_dir;
+        state.log_write_failures = log_write_failures;
+        if log_write_failures > 0 {
+            log::warn!("{} log files could not be written", log_write_failures);
+        }
+        if let Some(metrics) = self.metrics {
+            metrics.finish(&state);
+        }
+        if self.options.otlp_endpoint.is_some() || self.options.otlp_file.is_some() {
+            export_trace(command, &state, status, &self.options);
+        }
+        if self.options.output_format == OutputFormat::Ndjson {
+            emit(run_finished(&state, status));
+        }
+        state
+    }
+}
+
+pub fn run_tracked(
+    cmd: &mut PC::Command,
+    options: &RunOptions,
+) -> Result<(CommandState, ExitStatus), Error> {
+    let stats_file = enable_eval_stats(cmd);
+    let mut p = cmd.stderr(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+    let mut live_run = match LiveRun::new(options) {
+        Ok(live_run) => live_run,
+        Err(err) => {
+            let _ = p.kill();
+            return Err(err);
+        }
+    };
+    // read aside so that nix never blocks on a full stdout
+    let outputs = p
+        .stdout
+        .take()
+        .map(|stdout| thread::spawn(move || store_paths(stdout)));
+    match p.stderr.take() {
+        Some(stderr) => {
+            let mut reader = BufReader::new(stderr);
+            // one buffer for the whole stream, the parser borrows from it
+            let mut buf: Vec<u8> = Vec::new();
+            while let Ok(n) = reader.read_until(b'\n', &mut buf) {
+                if n == 0 {
+                    break;
+                }
+                live_run.line(&String::from_utf8_lossy(&buf));
+                buf.clear();
+            }
+        }
+        None => log::error!("Could not capture standard output error."),
+    }
+    let status = p.wait()?;
+    let mut state = live_run.finish(&status, &command_line(cmd));
+    EvalReport::collect_stats(&mut state, stats_file.as_deref());
+    state.command = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy().to_string())
+        .collect();
+    state.outputs = outputs
+        .and_then(|outputs| outputs.join().ok())
+        .unwrap_or_default();
+    if status.success() {
+        ClosureReport::collect(&mut state);
+    }
+    Ok((state, status))
+}
+
+/// The store paths printed by nix, like the outputs of `nix build --print-out-paths` or `nix-build`.
+fn store_paths(stdout: ChildStdout) -> Vec<String> {
+    BufReader::new(stdout)
+        .lines()
+        .map_while(Result::ok)
+        .map(|line| line.trim().to_owned())
+        .filter(|line| line.starts_with("/nix/store/"))
+        .collect()
+}
+
+fn command_line(cmd: &PC::Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn export_trace(command: &str, state: &CommandState, status: &ExitStatus, options: &RunOptions) {
+    let trace = trace(state, command, status);
+    if let Some(file) = &options.otlp_file {
+        match write_trace(file, &trace) {
+            Ok(()) => log::info!("trace written to {}", file.display()),
+            Err(err) => log::warn!("unable to write the trace to {}: {}", file.display(), err),
+        }
+    }
+    if let Some(endpoint) = &options.otlp_endpoint {
+        if let Err(err) = send_trace(endpoint, &trace) {
+            log::warn!("unable to send the trace to {}: {}", endpoint, err);
+        }
+    }
+}
+
+/// Stores the run and writes the reports asked for in the options, the regressions against
+/// the baseline are printed and returned.
+pub fn finish_run(
+    state: CommandState,
+    options: &RunOptions,
+) -> (JSONCommandState, Vec<Regression>) {
+    let run_dir = state.run_dir.clone();
+    let json_state = dump_state_to_file(state);
+    if let Some(junit) = &options.junit {
+        match write_junit_report(junit, &json_state, run_dir.as_deref()) {
+            Ok(()) => log::info!("JUnit report written to {}", junit.display()),
+            Err(err) => log::warn!("unable to write {}: {}", junit.display(), err),
+        }
+    }
+    if let OutputFormat::Ci(provider) = options.output_format {
+        print_annotations(provider, &json_state.errors);
+        write_step_summary(provider, &json_state);
+    }
+    let regressions = match regressions(&json_state, options) {
+        Some((run, regressions)) => {
+            print_regressions(&run, &regressions);
+            regressions
+        }
+        None => Vec::new(),
+    };
+    (json_state, regressions)
+}
+
+/// The regressions of a run against the baseline, if one is set.
+fn regressions(
+    state: &JSONCommandState,
+    options: &RunOptions,
+) -> Option<(String, Vec<Regression>)> {
+    let baseline = load_baseline()?;
+    let threshold = options
+        .regression_threshold
+        .unwrap_or(DEFAULT_THRESHOLD_PERCENT);
+    Some((baseline.run, compare(&baseline.state, state, threshold)))
+}
+
+/// Fails on regressions against the baseline when the options ask for it.
+pub fn check_regressions(regressions: &[Regression], options: &RunOptions) -> Result<(), Error> {
+    match options.fail_on_regression && !regressions.is_empty() {
+        true => Err(Error::other(format!(
+            "{} regression(s) against the baseline",
+            regressions.len()
+        ))),
+        false => Ok(()),
+    }
+}
+
+pub fn check_status(status: ExitStatus) -> Result<(), Error> {
+    match status.success() {
+        true => Ok(()),
+        false => Err(Error::other("Nix build failed")),
+    }
+}
@@ -0,0 +1,769 @@
+use crate::nix_history::{
+    log_store::{log_path, read_index, LogIndexEntry},
+    runs::{list_runs, load_run, run_id},
+};
+use crate::nix_logs::parser::get_package_from_drv;
+use crate::nix_tracker::{
+    format::{encode_base64, format_size, seconds},
+    types::JSONCommandState,
+};
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    Frame, Terminal,
+};
+use regex::Regex;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Error, ErrorKind, IsTerminal, Stdout, Write},
+    panic,
+    path::PathBuf,
+    time::SystemTime,
+};
+
+const VIEW_USAGE: &str = "usage: nixv view [<run>]
+browses a finished run, the latest one by default";
+
+const HELP: &str = "j/k move  enter fold  tab log  / regex  p package  l level  e first error  y copy path  q quit";
+
+/// Lines scrolled by page up and page down in the tree.
+const PAGE: usize = 10;
+
+/// Build logs have no level, it is guessed from the words of the line.
+#[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Clone, Copy)]
+enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Level {
+    fn of(line: &str) -> Level {
+        let lower = line.to_lowercase();
+        if lower.contains("error") || lower.contains("failed") {
+            Level::Error
+        } else if lower.contains("warning") {
+            Level::Warning
+        } else {
+            Level::Info
+        }
+    }
+
+    fn next(self) -> Level {
+        match self {
+            Level::Info => Level::Warning,
+            Level::Warning => Level::Error,
+            Level::Error => Level::Info,
+        }
+    }
+
+    fn style(self) -> Style {
+        match self {
+            Level::Info => Style::default(),
+            Level::Warning => Style::default().fg(Color::Yellow),
+            Level::Error => Style::default().fg(Color::Red),
+        }
+    }
+}
+
+/// A row of the activity tree, children follow their parent with a greater depth.
+struct Node {
+    depth: usize,
+    label: String,
+    package: Option<String>,
+    store_path: Option<String>,
+    /// the build whose log is shown
+    drv_path: Option<String>,
+    details: Vec<String>,
+    failed: bool,
+    folded: bool,
+    has_children: bool,
+}
+
+impl Node {
+    fn group(label: &str, count: usize) -> Node {
+        Node {
+            depth: 0,
+            label: format!("{} ({})", label, count),
+            package: None,
+            store_path: None,
+            drv_path: None,
+            details: Vec::new(),
+            failed: false,
+            folded: false,
+            has_children: count > 0,
+        }
+    }
+
+    fn activity(label: String, package: &str, store_path: &str) -> Node {
+        Node {
+            depth: 1,
+            label,
+            package: Some(package.to_owned()),
+            store_path: Some(store_path.to_owned()),
+            drv_path: None,
+            details: vec![store_path.to_owned()],
+            failed: false,
+            folded: false,
+            has_children: false,
+        }
+    }
+}
+
+/// `started +1.2s, took 3.4s` against the start of the run.
+fn timing(state: &JSONCommandState, start: SystemTime, end: SystemTime) -> String {
+    format!(
+        "started +{:.1}s, took {:.1}s",
+        seconds(state.start, start),
+        seconds(start, end)
+    )
+}
+
+/// The groups of the top of the tree, in order.
+const GROUPS: [&str; 5] = ["builds", "substitutions", "copies", "downloads", "errors"];
+
+/// An activity of the tree: its node, then the phases of a build.
+struct Entry {
+    id: Option<i64>,
+    group: usize,
+    start: SystemTime,
+    nodes: Vec<Node>,
+}
+
+/// The nearest activity `id` runs under that has an entry, nix puts others like the realisation
+/// in between.
+fn shown_parent(id: i64, parents: &HashMap<i64, i64>, shown: &HashSet<i64>) -> Option<i64> {
+    let mut id = id;
+    // the parents come from the log, a loop in them must not hang the viewer
+    for _ in 0..parents.len() {
+        id = *parents.get(&id)?;
+        if shown.contains(&id) {
+            return Some(id);
+        }
+    }
+    None
+}
+
+/// Pushes the nodes of `entry` at `depth`, followed by the activities that run under it.
+fn push_entry(
+    nodes: &mut Vec<Node>,
+    entry: Entry,
+    depth: usize,
+    children: &mut HashMap<i64, Vec<Entry>>,
+) {
+    let kids = entry
+        .id
+        .and_then(|id| children.remove(&id))
+        .unwrap_or_default();
+    let mut own = entry.nodes.into_iter();
+    if let Some(mut node) = own.next() {
+        node.depth = depth;
+        node.has_children |= !kids.is_empty();
+        nodes.push(node);
+    }
+    for mut phase in own {
+        phase.depth = depth + 1;
+        nodes.push(phase);
+    }
+    for kid in kids {
+        push_entry(nodes, kid, depth + 1, children);
+    }
+}
+
+/// The builds with their phases, then the substitutions, copies, downloads and errors, each
+/// activity under the one it runs under: a download under its substitution for instance.
+fn activity_tree(state: &JSONCommandState) -> Vec<Node> {
+    let mut entries = Vec::new();
+    for build in state.act_build.iter() {
+        let host = match build.host.as_str() {
+            "" | "local" => String::new(),
+            host => format!(" on {}", host),
+        };
+        let mut node = Node::activity(
+            format!(
+                "{} {:.1}s{}",
+                build.package_name,
+                seconds(build.start, build.end),
+                host
+            ),
+            &build.package_name,
+            &build.store_path,
+        );
+        node.drv_path = Some(build.store_path.to_owned());
+        node.details
+            .push(timing(state, build.start, build.end) + &host);
+        node.failed = state
+            .errors
+            .iter()
+            .any(|e| e.drv_path() == Some(build.store_path.as_str()));
+        node.folded = true;
+        node.has_children = !build.phases.is_empty();
+        let mut nodes = vec![node];
+        for phase in build.phases.iter() {
+            let mut node = Node::activity(
+                format!("{} {:.1}s", phase.name, seconds(phase.start, phase.end)),
+                &build.package_name,
+                &build.store_path,
+            );
+            node.drv_path = Some(build.store_path.to_owned());
+            nodes.push(node);
+        }
+        entries.push(Entry {
+            id: Some(build.id),
+            group: 0,
+            start: build.start,
+            nodes,
+        });
+    }
+    for s in state.act_substitute.iter() {
+        let size = s.size.map(|s| format!(" {}", format_size(s)));
+        let mut node = Node::activity(
+            format!("{}{}", s.package_name, size.unwrap_or_default()),
+            &s.package_name,
+            &s.store_path,
+        );
+        node.details.push(format!("from {}", s.from));
+        node.details.push(timing(state, s.start, s.end));
+        entries.push(Entry {
+            id: Some(s.id),
+            group: 1,
+            start: s.start,
+            nodes: vec![node],
+        });
+    }
+    for c in state.act_copy_path.iter() {
+        let mut node = Node::activity(c.package_name.to_owned(), &c.package_name, &c.store_path);
+        node.details.push(format!("from {} to {}", c.from, c.to));
+        node.details.push(timing(state, c.start, c.end));
+        entries.push(Entry {
+            id: Some(c.id),
+            group: 2,
+            start: c.start,
+            nodes: vec![node],
+        });
+    }
+    for t in state.act_file_transfer.iter() {
+        let mut node = Node::activity(t.file.to_owned(), &t.file, &t.file);
+        node.store_path = None;
+        node.details.push(timing(state, t.start, t.end));
+        entries.push(Entry {
+            id: Some(t.id),
+            group: 3,
+            start: t.start,
+            nodes: vec![node],
+        });
+    }
+    for err in state.errors.iter() {
+        let summary = err.summary();
+        let drv_path = err.drv_path().unwrap_or_default();
+        let package = get_package_from_drv(drv_path.to_owned());
+        let mut node = Node::activity(
+            summary.lines().next().unwrap_or_default().to_owned(),
+            &package,
+            drv_path,
+        );
+        node.store_path = err.drv_path().map(|d| d.to_owned());
+        node.drv_path = node.store_path.clone();
+        node.details = summary.lines().map(|l| l.to_owned()).collect();
+        node.failed = true;
+        entries.push(Entry {
+            id: None,
+            group: 4,
+            start: state.end,
+            nodes: vec![node],
+        });
+    }
+    // runs stored without the parents have every activity at the top
+    let shown: HashSet<i64> = entries.iter().filter_map(|e| e.id).collect();
+    let mut roots: Vec<Vec<Entry>> = GROUPS.iter().map(|_| Vec::new()).collect();
+    let mut children: HashMap<i64, Vec<Entry>> = HashMap::new();
+    entries.sort_by_key(|e| e.start);
+    for entry in entries {
+        match entry
+            .id
+            .and_then(|id| shown_parent(id, &state.activity_parents, &shown))
+        {
+            Some(parent) => children.entry(parent).or_default().push(entry),
+            None => roots[entry.group].push(entry),
+        }
+    }
+    let mut nodes = Vec::new();
+    for (group, entries) in GROUPS.iter().zip(roots) {
+        nodes.push(Node::group(group, entries.len()));
+        for entry in entries {
+            push_entry(&mut nodes, entry, 1, &mut children);
+        }
+    }
+    nodes
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum Focus {
+    Tree,
+    Log,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum Prompt {
+    Regex,
+    Package,
+}
+
+struct View {
+    run: String,
+    run_dir: PathBuf,
+    nodes: Vec<Node>,
+    /// indexes of the nodes shown, after folding and the package filter
+    visible: Vec<usize>,
+    tree: ListState,
+    index: Vec<LogIndexEntry>,
+    logs: HashMap<String, Vec<String>>,
+    focus: Focus,
+    log_scroll: usize,
+    log_height: usize,
+    regex: Option<Regex>,
+    package: String,
+    level: Level,
+    input: Option<(Prompt, String)>,
+    status: String,
+}
+
+impl View {
+    fn new(run: String, run_dir: PathBuf, state: &JSONCommandState) -> View {
+        let mut view = View {
+            index: read_index(&run_dir),
+            run,
+            run_dir,
+            nodes: activity_tree(state),
+            visible: Vec::new(),
+            tree: ListState::default(),
+            logs: HashMap::new(),
+            focus: Focus::Tree,
+            log_scroll: 0,
+            log_height: 0,
+            regex: None,
+            package: String::new(),
+            level: Level::Info,
+            input: None,
+            status: HELP.to_owned(),
+        };
+        view.refresh();
+        view.tree.select(Some(0));
+        view
+    }
+
+    /// Recomputes the visible nodes, keeping the selected one when it is still shown.
+    fn refresh(&mut self) {
+        let selected = self.selected_index();
+        let package = self.package.to_lowercase();
+        let mut visible = Vec::new();
+        let mut folded_at: Option<usize> = None;
+        for (i, node) in self.nodes.iter().enumerate() {
+            match folded_at {
+                Some(depth) if node.depth > depth => continue,
+                _ => folded_at = None,
+            }
+            let matches = match &node.package {
+                Some(p) => p.to_lowercase().contains(&package),
+                None => true,
+            };
+            if !matches {
+                continue;
+            }
+            visible.push(i);
+            if node.folded && node.has_children {
+                folded_at = Some(node.depth);
+            }
+        }
+        self.visible = visible;
+        let position = selected.and_then(|s| self.visible.iter().position(|i| *i == s));
+        self.tree.select(match self.visible.is_empty() {
+            true => None,
+            false => Some(position.unwrap_or(0)),
+        });
+    }
+
+    fn selected_index(&self) -> Option<usize> {
+        self.tree
+            .selected()
+            .and_then(|s| self.visible.get(s).copied())
+    }
+
+    fn selected(&self) -> Option<&Node> {
+        self.selected_index().map(|i| &self.nodes[i])
+    }
+
+    fn select(&mut self, position: usize) {
+        if !self.visible.is_empty() {
+            self.tree.select(Some(position.min(self.visible.len() - 1)));
+            self.log_scroll = 0;
+        }
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        let position = self.tree.selected().unwrap_or(0);
+        self.select(position.saturating_add_signed(delta));
+    }
+
+    fn fold(&mut self, folded: Option<bool>) {
+        if let Some(i) = self.selected_index() {
+            let node = &mut self.nodes[i];
+            node.folded = folded.unwrap_or(!node.folded);
+            self.refresh();
+        }
+    }
+
+    /// The build log of a derivation, read once.
+    fn log(&mut self, drv_path: &str) -> &[String] {
+        if !self.logs.contains_key(drv_path) {
+            let lines = self
+                .index
+                .iter()
+                .find(|e| e.drv_path == drv_path)
+                .and_then(|e| fs::read_to_string(log_path(&self.run_dir, e)).ok())
+                .map(|content| content.lines().map(|l| l.to_owned()).collect())
+                .unwrap_or_default();
+            self.logs.insert(drv_path.to_owned(), lines);
+        }
+        &self.logs[drv_path]
+    }
+
+    /// The lines of the log of the selected node that pass the level and regex filters, with
+    /// their line number.
+    fn shown_lines(&mut self) -> Vec<(usize, String)> {
+        let drv_path = match self.selected().and_then(|n| n.drv_path.clone()) {
+            Some(drv_path) => drv_path,
+            None => return Vec::new(),
+        };
+        let (level, regex) = (self.level, self.regex.clone());
+        self.log(&drv_path)
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| Level::of(line) >= level)
+            .filter(|(_, line)| match &regex {
+                Some(re) => re.is_match(line),
+                None => true,
+            })
+            .map(|(n, line)| (n + 1, line.to_owned()))
+            .collect()
+    }
+
+    /// Selects the first failed build, or error, and scrolls its log to the first error line.
+    fn first_error(&mut self) {
+        let failed = self
+            .nodes
+            .iter()
+            .position(|n| n.failed && n.drv_path.is_some())
+            .or_else(|| self.nodes.iter().position(|n| n.failed));
+        let i = match failed {
+            Some(i) => i,
+            None => {
+                self.status = "no error in this run".to_owned();
+                return;
+            }
+        };
+        // unfold the nodes above it and make sure the package filter lets it through
+        let mut depth = self.nodes[i].depth;
+        for node in self.nodes[..i].iter_mut().rev() {
+            if node.depth < depth {
+                node.folded = false;
+                depth = node.depth;
+            }
+        }
+        self.package.clear();
+        self.refresh();
+        if let Some(position) = self.visible.iter().position(|v| *v == i) {
+            self.select(position);
+        }
+        let lines = self.shown_lines();
+        self.log_scroll = lines
+            .iter()
+            .position(|(_, line)| Level::of(line) == Level::Error)
+            .unwrap_or(lines.len().saturating_sub(self.log_height));
+        self.focus = Focus::Log;
+        self.status = format!("first error: {}", self.nodes[i].label);
+    }
+
+    fn copy_store_path(&mut self) {
+        let path = match self.selected().and_then(|n| n.store_path.clone()) {
+            Some(path) => path,
+            None => {
+                self.status = "no store path to copy".to_owned();
+                return;
+            }
+        };
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "\x1b]52;c;{}\x07", encode_base64(path.as_bytes()));
+        let _ = stdout.flush();
+        self.status = format!("copied {}", path);
+    }
+
+    fn apply_input(&mut self, prompt: Prompt, text: String) {
+        match prompt {
+            Prompt::Regex if text.is_empty() => self.regex = None,
+            Prompt::Regex => match Regex::new(&text) {
+                Ok(re) => self.regex = Some(re),
+                Err(err) => self.status = format!("invalid regex: {}", err),
+            },
+            Prompt::Package => {
+                self.package = text;
+                self.refresh();
+            }
+        }
+        self.log_scroll = 0;
+    }
+
+    /// Handles a key, returns false to quit.
+    fn key(&mut self, key: KeyEvent) -> bool {
+        if let Some((prompt, mut text)) = self.input.take() {
+            match key.code {
+                KeyCode::Enter => self.apply_input(prompt, text),
+                KeyCode::Esc => {}
+                KeyCode::Backspace => {
+                    text.pop();
+                    self.input = Some((prompt, text));
+                }
+                KeyCode::Char(c) => {
+                    text.push(c);
+                    self.input = Some((prompt, text));
+                }
+                _ => self.input = Some((prompt, text)),
+            }
+            return true;
+        }
+        let page = self.log_height.max(1);
+        match (self.focus, key.code) {
+            (_, KeyCode::Char('q')) | (_, KeyCode::Esc) => return false,
+            (_, KeyCode::Tab) => {
+                self.focus = match self.focus {
+                    Focus::Tree => Focus::Log,
+                    Focus::Log => Focus::Tree,
+                }
+            }
+            (_, KeyCode::Char('/')) => {
+                let current = self.regex.as_ref().map(|r| r.to_string());
+                self.input = Some((Prompt::Regex, current.unwrap_or_default()));
+            }
+            (_, KeyCode::Char('p')) => self.input = Some((Prompt::Package, self.package.clone())),
+            (_, KeyCode::Char('l')) => {
+                self.level = self.level.next();
+                self.log_scroll = 0;
+            }
+            (_, KeyCode::Char('e')) => self.first_error(),
+            (_, KeyCode::Char('y')) => self.copy_store_path(),
+            (Focus::Tree, KeyCode::Up | KeyCode::Char('k')) => self.move_by(-1),
+            (Focus::Tree, KeyCode::Down | KeyCode::Char('j')) => self.move_by(1),
+            (Focus::Tree, KeyCode::PageUp) => self.move_by(-(PAGE as isize)),
+            (Focus::Tree, KeyCode::PageDown) => self.move_by(PAGE as isize),
+            (Focus::Tree, KeyCode::Home | KeyCode::Char('g')) => self.select(0),
+            (Focus::Tree, KeyCode::End | KeyCode::Char('G')) => self.select(usize::MAX),
+            (Focus::Tree, KeyCode::Enter | KeyCode::Char(' ')) => self.fold(None),
+            (Focus::Tree, KeyCode::Left | KeyCode::Char('h')) => self.fold(Some(true)),
+            (Focus::Tree, KeyCode::Right) => self.fold(Some(false)),
+            (Focus::Log, KeyCode::Up | KeyCode::Char('k')) => {
+                self.log_scroll = self.log_scroll.saturating_sub(1)
+            }
+            (Focus::Log, KeyCode::Down | KeyCode::Char('j')) => self.log_scroll += 1,
+            (Focus::Log, KeyCode::PageUp) => self.log_scroll = self.log_scroll.saturating_sub(page),
+            (Focus::Log, KeyCode::PageDown) => self.log_scroll += page,
+            (Focus::Log, KeyCode::Home | KeyCode::Char('g')) => self.log_scroll = 0,
+            (Focus::Log, KeyCode::End | KeyCode::Char('G')) => self.log_scroll = usize::MAX,
+            _ => {}
+        }
+        true
+    }
+
+    fn border(&self, focus: Focus) -> Style {
+        match self.focus == focus {
+            true => Style::default().fg(Color::Yellow),
+            false => Style::default(),
+        }
+    }
+
+    fn draw(&mut self, f: &mut Frame) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(1)])
+            .split(f.size());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+            .split(rows[0]);
+
+        let items: Vec<ListItem> = self
+            .visible
+            .iter()
+            .map(|i| {
+                let node = &self.nodes[*i];
+                let marker = match (node.has_children, node.folded) {
+                    (false, _) => "  ",
+                    (true, true) => "▸ ",
+                    (true, false) => "▾ ",
+                };
+                let style = match node.failed {
+                    true => Style::default().fg(Color::Red),
+                    false => Style::default(),
+                };
+                ListItem::new(Line::from(Span::styled(
+                    format!("{}{}{}", "  ".repeat(node.depth), marker, node.label),
+                    style,
+                )))
+            })
+            .collect();
+        let mut title = format!(" run {} ", self.run);
+        if !self.package.is_empty() {
+            title += &format!("package ~ {} ", self.package);
+        }
+        let tree = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(self.border(Focus::Tree))
+                    .title(title),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        f.render_stateful_widget(tree, columns[0], &mut self.tree);
+
+        self.log_height = columns[1].height.saturating_sub(2) as usize;
+        let lines = self.shown_lines();
+        self.log_scroll = self
+            .log_scroll
+            .min(lines.len().saturating_sub(self.log_height));
+        let node = self.selected();
+        let (title, text): (String, Vec<Line>) = match node {
+            Some(node) if node.drv_path.is_some() => {
+                let total = node
+                    .drv_path
+                    .as_ref()
+                    .and_then(|d| self.logs.get(d))
+                    .map_or(0, |l| l.len());
+                let mut title = format!(
+                    " log of {} ({}/{} lines",
+                    node.package.as_deref().unwrap_or_default(),
+                    lines.len(),
+                    total
+                );
+                if self.level > Level::Info {
+                    title += &format!(", level >= {:?}", self.level).to_lowercase();
+                }
+                if let Some(re) = &self.regex {
+                    title += &format!(", /{}/", re);
+                }
+                title += ") ";
+                let text = lines
+                    .iter()
+                    .skip(self.log_scroll)
+                    .take(self.log_height)
+                    .map(|(n, line)| {
+                        Line::from(vec![
+                            Span::styled(
+                                format!("{:>5} ", n),
+                                Style::default().fg(Color::DarkGray),
+                            ),
+                            Span::styled(line.to_owned(), Level::of(line).style()),
+                        ])
+                    })
+                    .collect();
+                (title, text)
+            }
+            Some(node) => (
+                format!(" {} ", node.label),
+                node.details
+                    .iter()
+                    .map(|d| Line::from(d.to_owned()))
+                    .collect(),
+            ),
+            None => (" nothing selected ".to_owned(), Vec::new()),
+        };
+        let log = Paragraph::new(text).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(self.border(Focus::Log))
+                .title(title),
+        );
+        f.render_widget(log, columns[1]);
+
+        let status = match &self.input {
+            Some((Prompt::Regex, text)) => format!("regex: {}", text),
+            Some((Prompt::Package, text)) => format!("package: {}", text),
+            None => self.status.clone(),
+        };
+        f.render_widget(
+            Paragraph::new(status).style(Style::default().add_modifier(Modifier::BOLD)),
+            rows[1],
+        );
+    }
+}
+
+/// Raw mode and the alternate screen, given back when dropped: when the view ends, fails to
+/// start or panics.
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enter() -> io::Result<RawTerminal> {
+        // the hook runs before unwinding, the message would be lost on the alternate screen
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            hook(info);
+        }));
+        enable_raw_mode()?;
+        let raw = RawTerminal;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        Ok(raw)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen, cursor::Show);
+}
+
+fn run_view(terminal: &mut Terminal<CrosstermBackend<Stdout>>, view: &mut View) -> io::Result<()> {
+    loop {
+        terminal.draw(|f| view.draw(f))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && !view.key(key) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub fn view_process(args: Vec<String>) -> Result<(), Error> {
+    let run = match args.as_slice() {
+        [] => match list_runs().first() {
+            Some(latest) => run_id(latest),
+            None => return Err(Error::new(ErrorKind::NotFound, "no runs recorded yet")),
+        },
+        [run] if !run.starts_with('-') => run.to_owned(),
+        _ => return Err(Error::new(ErrorKind::InvalidInput, VIEW_USAGE)),
+    };
+    let (run_dir, state) = load_run(&run).ok_or(Error::new(
+        ErrorKind::NotFound,
+        format!("no run found for {}", run),
+    ))?;
+    if !io::stdout().is_terminal() {
+        return Err(Error::other("nixv view needs a terminal"));
+    }
+    let mut view = View::new(run_id(&run_dir), run_dir, &state);
+    let _raw = RawTerminal::enter()?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    run_view(&mut terminal, &mut view)
+}
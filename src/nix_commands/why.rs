@@ -0,0 +1,242 @@
+use crate::nix_history::runs::{find_run, list_runs, read_run_state, run_id};
+use crate::nix_tracker::{
+    drv_diff::{derivers, path_name, show_derivations, Derivation, DrvDiff},
+    eta::pname,
+    types::{JSONActBuild, JSONCommandState},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Error, ErrorKind},
+    path::PathBuf,
+    process::{self as PC, Stdio},
+};
+
+const WHY_USAGE: &str = "usage: nixv why <pkg-or-drv> [--run <run>] [--from <installable>]
+shows what depends on a rebuilt derivation and what changed since it was last built or substituted";
+
+struct WhyArgs {
+    query: String,
+    run: Option<String>,
+    from: Option<String>,
+}
+
+fn parse_args(args: Vec<String>) -> Result<WhyArgs, Error> {
+    let usage = || Error::new(ErrorKind::InvalidInput, WHY_USAGE);
+    let (mut query, mut run, mut from) = (None, None, None);
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--run" => run = Some(args.next().ok_or_else(usage)?),
+            "--from" => from = Some(args.next().ok_or_else(usage)?),
+            _ if query.is_none() && !arg.starts_with('-') => query = Some(arg),
+            _ => return Err(usage()),
+        }
+    }
+    Ok(WhyArgs {
+        query: query.ok_or_else(usage)?,
+        run,
+        from,
+    })
+}
+
+fn failed(state: &JSONCommandState, drv_path: &str) -> bool {
+    state.errors.iter().any(|e| e.drv_path() == Some(drv_path))
+}
+
+/// Matches a derivation path or a package name exactly, otherwise a part of them.
+fn find_build<'a>(state: &'a JSONCommandState, query: &str) -> Option<&'a JSONActBuild> {
+    state
+        .act_build
+        .iter()
+        .find(|b| b.store_path == query || b.package_name == query)
+        .or_else(|| {
+            state
+                .act_build
+                .iter()
+                .find(|b| b.store_path.contains(query) || b.package_name.contains(query))
+        })
+}
+
+/// The derivation the run was asked for: the deriver of its outputs, or else the last build
+/// it started since everything else it built is a dependency of it.
+fn requested(state: &JSONCommandState) -> Option<String> {
+    if !state.outputs.is_empty() {
+        if let Ok(derivers) = derivers(&state.outputs) {
+            if let Some(deriver) = state.outputs.iter().find_map(|o| derivers.get(o)) {
+                return Some(deriver.to_owned());
+            }
+        }
+    }
+    state
+        .act_build
+        .iter()
+        .max_by_key(|b| b.start)
+        .map(|b| b.store_path.to_owned())
+}
+
+/// The derivation of the latest earlier run that built or substituted `package_name`, looked
+/// up by name and version first, then by package name.
+fn previous_derivation(
+    runs: &[PathBuf],
+    package_name: &str,
+) -> Option<(String, String, &'static str)> {
+    let states: Vec<(String, JSONCommandState)> = runs
+        .iter()
+        .filter_map(|run_dir| Some((run_id(run_dir), read_run_state(run_dir)?)))
+        .collect();
+    let keys: [fn(&str) -> &str; 2] = [|name| name, pname];
+    for key in keys {
+        let wanted = key(package_name);
+        for (run, state) in states.iter() {
+            let built = state
+                .act_build
+                .iter()
+                .find(|b| key(&b.package_name) == wanted && !failed(state, &b.store_path));
+            if let Some(build) = built {
+                return Some((run.to_owned(), build.store_path.to_owned(), "built"));
+            }
+            let substituted = state
+                .act_substitute
+                .iter()
+                .find(|s| key(&s.package_name) == wanted);
+            if let Some(substitute) = substituted {
+                let path = substitute.store_path.to_owned();
+                if let Some(deriver) = derivers(std::slice::from_ref(&path))
+                    .ok()
+                    .and_then(|d| d.get(&path).cloned())
+                {
+                    return Some((run.to_owned(), deriver, "substituted"));
+                }
+            }
+        }
+    }
+    None
+}
+
+fn print_chain(from: &str, drv_path: &str) {
+    let output = PC::Command::new("nix")
+        .arg("--extra-experimental-features")
+        .arg("nix-command flakes")
+        .arg("why-depends")
+        .arg("--derivation")
+        .arg(from)
+        .arg(drv_path)
+        .stderr(Stdio::piped())
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            print!("{}", String::from_utf8_lossy(&output.stdout))
+        }
+        Ok(output) => log::warn!(
+            "nix why-depends failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(err) => log::warn!("unable to run nix why-depends: {}", err),
+    }
+}
+
+/// Prints the inputs that changed as a tree down to the derivations that changed themselves,
+/// which are added to `causes`.
+fn print_causes<'a>(
+    old: &'a Derivation,
+    new: &'a Derivation,
+    drvs: &'a HashMap<String, Derivation>,
+    depth: usize,
+    seen: &mut HashSet<String>,
+    causes: &mut Vec<(&'a str, Vec<String>)>,
+) {
+    let diff = DrvDiff::compare(old, new, drvs);
+    let own = diff.own_changes();
+    let indent = "  ".repeat(depth);
+    match own.is_empty() {
+        true => println!("{}{}", indent, path_name(&new.path)),
+        false => {
+            println!("{}{}: {}", indent, path_name(&new.path), own.join("; "));
+            causes.push((&new.path, own));
+        }
+    }
+    for (old, new) in diff.changed_inputs() {
+        if !seen.insert(new.to_owned()) {
+            println!("{}  {} (see above)", indent, path_name(new));
+            continue;
+        }
+        match (drvs.get(old), drvs.get(new)) {
+            (Some(old), Some(new)) => print_causes(old, new, drvs, depth + 1, seen, causes),
+            _ => println!("{}  {}", indent, path_name(new)),
+        }
+    }
+}
+
+pub fn why_process(args: Vec<String>) -> Result<(), Error> {
+    let args = parse_args(args)?;
+    let runs = list_runs();
+    let selected: Vec<PathBuf> = match &args.run {
+        Some(run) => vec![find_run(run).ok_or(Error::new(
+            ErrorKind::NotFound,
+            format!("no run found for {}", run),
+        ))?],
+        None => runs.clone(),
+    };
+    let (run_dir, state, build) = selected
+        .into_iter()
+        .find_map(|run_dir| {
+            let state = read_run_state(&run_dir)?;
+            let build = find_build(&state, &args.query)?.clone();
+            Some((run_dir, state, build))
+        })
+        .ok_or(Error::new(
+            ErrorKind::NotFound,
+            format!("no build of {} found", args.query),
+        ))?;
+    let run = run_id(&run_dir);
+    println!(
+        "{} was built in run {} ({})",
+        build.package_name, run, build.store_path
+    );
+
+    match args.from.or_else(|| requested(&state)) {
+        Some(from) if from != build.store_path => {
+            println!("\nneeded by {}:", from);
+            print_chain(&from, &build.store_path);
+        }
+        _ => println!("\nit is what the run was asked to build"),
+    }
+
+    let earlier: Vec<PathBuf> = runs.into_iter().filter(|r| run_id(r) < run).collect();
+    let (previous_run, previous, how) = match previous_derivation(&earlier, &build.package_name) {
+        Some(previous) => previous,
+        None => {
+            println!(
+                "\nno earlier run built or substituted {}",
+                pname(&build.package_name)
+            );
+            return Ok(());
+        }
+    };
+    if previous == build.store_path {
+        println!(
+            "\nthe derivation is the same as in run {}, where it was {}: it is rebuilt because its output is neither in the store nor in a binary cache anymore",
+            previous_run, how
+        );
+        return Ok(());
+    }
+    println!(
+        "\nchanged since run {}, where it was {} ({}):",
+        previous_run, how, previous
+    );
+    let drvs = show_derivations(&[previous.clone(), build.store_path.clone()], true)
+        .map_err(|err| Error::other(format!("{}, was {} garbage collected?", err, previous)))?;
+    let (old, new) = match (drvs.get(&previous), drvs.get(&build.store_path)) {
+        (Some(old), Some(new)) => (old, new),
+        _ => return Err(Error::other("nix derivation show left out a derivation")),
+    };
+    let mut causes = Vec::new();
+    print_causes(old, new, &drvs, 0, &mut HashSet::new(), &mut causes);
+    if !causes.is_empty() {
+        println!("\nchanged derivations:");
+        for (drv_path, changes) in causes {
+            println!("  {}: {}", path_name(drv_path), changes.join("; "));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::nix_tracker::types::JSONCommandState;
+
+use super::runs::nixv_dir;
+
+/// A run pinned to compare the next runs with, kept as a copy so that it outlives the run directory.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Baseline {
+    pub run: String,
+    pub set: SystemTime,
+    pub state: JSONCommandState,
+}
+
+fn baseline_file() -> PathBuf {
+    nixv_dir().join("baseline.json")
+}
+
+pub fn set_baseline(run: &str, state: JSONCommandState) -> Result<PathBuf, Error> {
+    let baseline = Baseline {
+        run: run.to_owned(),
+        set: SystemTime::now(),
+        state,
+    };
+    let file = baseline_file();
+    fs::create_dir_all(nixv_dir())?;
+    fs::write(&file, serde_json::to_string_pretty(&baseline)?)?;
+    Ok(file)
+}
+
+pub fn load_baseline() -> Option<Baseline> {
+    let file = baseline_file();
+    let content = fs::read_to_string(&file).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(baseline) => Some(baseline),
+        Err(err) => {
+            log::warn!("unable to read {}: {}", file.display(), err);
+            None
+        }
+    }
+}
+
+pub fn clear_baseline() -> Result<(), Error> {
+    match fs::remove_file(baseline_file()) {
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    nix_logs::{
+        log_sink::LogSender,
+        types::{Activity, ActivityResult, JSONMessage},
+    },
+    nix_tracker::types::CommandState,
+};
+
+pub const LOGS_DIR: &str = "logs";
+pub const INDEX_FILE: &str = "index.json";
+
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub struct LogIndexEntry {
+    pub drv_path: String,
+    pub package_name: String,
+    pub file: String,
+    pub lines: usize,
+}
+
+/// Build logs of a single run, one file per derivation under `<run>/logs`.
+pub struct LogStore {
+    dir: PathBuf,
+    sender: LogSender,
+    files: HashMap<String, LogIndexEntry>,
+}
+
+pub fn log_file_name(drv_path: &str) -> String {
+    let base = drv_path.rsplit('/').next().unwrap_or(drv_path);
+    format!("{}.log", base)
+}
+
+impl LogStore {
+    pub fn new(run_dir: &Path, sender: LogSender) -> LogStore {
+        let dir = run_dir.join(LOGS_DIR);
+        if let Err(err) = fs::create_dir_all(&dir) {
+            log::warn!("unable to create {}: {}", dir.display(), err);
+        }
+        LogStore {
+            dir,
+            sender,
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, id: i64, msg: &Option<JSONMessage>, state: &CommandState) {
+        let (package_name, drv_path) = match state.activity.get(&id).map(|a| &a.activity) {
+            Some(Activity::ActBuild(package_name, drv_path, _, _, _)) => (package_name, drv_path),
+            _ => return,
+        };
+        let line = match msg {
+            Some(JSONMessage::Result(res)) => match &res.result {
+                ActivityResult::BuildLogLine(line) => line,
+                ActivityResult::PostBuildLogLine(line) => line,
+                _ => return,
+            },
+            Some(JSONMessage::Stop(_)) => {
+                if let Some(entry) = self.files.get(drv_path) {
+                    self.sender.close(self.dir.join(&entry.file));
+                }
+                return;
+            }
+            _ => return,
+        };
+        self.append(drv_path, package_name, &strip_ansi_escapes::strip_str(line));
+    }
+
+    pub fn append(&mut self, drv_path: &str, package_name: &str, line: &str) {
+        let entry = self
+            .files
+            .entry(drv_path.to_owned())
+            .or_insert_with(|| LogIndexEntry {
+                drv_path: drv_path.to_owned(),
+                package_name: package_name.to_owned(),
+                file: log_file_name(drv_path),
+                lines: 0,
+            });
+        entry.lines += 1;
+        self.sender
+            .append(self.dir.join(&entry.file), line.to_owned());
+    }
+
+    /// Writes the index of the run, the logs themselves are flushed by the sink.
+    pub fn finish(self) {
+        let mut index: Vec<LogIndexEntry> = self.files.into_values().collect();
+        index.sort_by(|a, b| a.package_name.cmp(&b.package_name));
+        match File::create(self.dir.join(INDEX_FILE)) {
+            Ok(mut file) => {
+                let json_dump = serde_json::to_string_pretty(&index).unwrap();
+                let _ = file.write_all(json_dump.as_bytes());
+            }
+            Err(err) => log::warn!("unable to write the log index: {}", err),
+        }
+    }
+}
+
+pub fn read_index(run_dir: &Path) -> Vec<LogIndexEntry> {
+    fs::read_to_string(run_dir.join(LOGS_DIR).join(INDEX_FILE))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+pub fn log_path(run_dir: &Path, entry: &LogIndexEntry) -> PathBuf {
+    run_dir.join(LOGS_DIR).join(&entry.file)
+}
+
+/// The last `lines` lines of the stored build log of a derivation.
+pub fn log_tail(run_dir: &Path, drv_path: &str, lines: usize) -> Vec<String> {
+    let content = fs::read_to_string(run_dir.join(LOGS_DIR).join(log_file_name(drv_path)))
+        .unwrap_or_default();
+    let all: Vec<&str> = content.lines().collect();
+    all[all.len().saturating_sub(lines)..]
+        .iter()
+        .map(|l| l.to_string())
+        .collect()
+}
+
+/// Matches a derivation path exactly, otherwise a package name or a part of it.
+pub fn find_entries<'a>(index: &'a [LogIndexEntry], query: &str) -> Vec<&'a LogIndexEntry> {
+    let exact: Vec<&LogIndexEntry> = index
+        .iter()
+        .filter(|e| e.drv_path == query || e.package_name == query)
+        .collect();
+    match exact.is_empty() {
+        true => index
+            .iter()
+            .filter(|e| e.drv_path.contains(query))
+            .collect(),
+        false => exact,
+    }
+}
@@ -0,0 +1,3 @@
+pub mod baseline;
+pub mod log_store;
+pub mod runs;
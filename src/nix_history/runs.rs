@@ -0,0 +1,117 @@
+use std::{
+    env, fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+
+use crate::nix_tracker::types::JSONCommandState;
+
+pub const STATE_FILE: &str = "command_state.json";
+
+/// Directory holding every run, `.nixv` in the current directory unless ENV `NIXV_DIR` is set.
+pub fn nixv_dir() -> PathBuf {
+    match env::var("NIXV_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(".nixv"),
+    }
+}
+
+pub fn runs_dir() -> PathBuf {
+    nixv_dir().join("runs")
+}
+
+/// Creates a new run directory named after the current time, so that names sort by age.
+///
+/// Runs started within the same millisecond get a `-<n>` suffix.
+pub fn create_run_dir() -> Result<PathBuf, Error> {
+    let id = Utc::now().format("%Y%m%dT%H%M%S%.3fZ").to_string();
+    let runs = runs_dir();
+    fs::create_dir_all(&runs)?;
+    let mut dir = runs.join(&id);
+    let mut n = 0;
+    loop {
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                n += 1;
+                dir = runs.join(format!("{}-{}", id, n));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// All run directories, newest first.
+pub fn list_runs() -> Vec<PathBuf> {
+    let mut runs: Vec<PathBuf> = match fs::read_dir(runs_dir()) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_dir())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    runs.sort();
+    runs.reverse();
+    runs
+}
+
+pub fn run_id(run_dir: &Path) -> String {
+    run_dir
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Resolves a run given either its id, a path to a run directory or a path to a state file.
+pub fn find_run(run: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(run);
+    if path.is_dir() {
+        return Some(path);
+    }
+    if path.is_file() {
+        return path.parent().map(|p| p.to_path_buf());
+    }
+    let dir = runs_dir().join(run);
+    match dir.is_dir() {
+        true => Some(dir),
+        false => None,
+    }
+}
+
+pub fn read_state_file(file: &Path) -> Option<JSONCommandState> {
+    let content = fs::read_to_string(file).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(state) => Some(state),
+        Err(err) => {
+            log::warn!("unable to read {}: {}", file.display(), err);
+            None
+        }
+    }
+}
+
+pub fn write_run_state(run_dir: &Path, state: &JSONCommandState) -> Result<(), Error> {
+    fs::write(
+        run_dir.join(STATE_FILE),
+        serde_json::to_string_pretty(state)?,
+    )
+}
+
+pub fn read_run_state(run_dir: &Path) -> Option<JSONCommandState> {
+    read_state_file(&run_dir.join(STATE_FILE))
+}
+
+/// The state of a run given like [`find_run`], or of any state file, with its run directory.
+pub fn load_run(run: &str) -> Option<(PathBuf, JSONCommandState)> {
+    let path = PathBuf::from(run);
+    if path.is_file() {
+        let state = read_state_file(&path)?;
+        let run_dir = path.parent()?.to_path_buf();
+        return Some((run_dir, state));
+    }
+    let run_dir = find_run(run)?;
+    let state = read_run_state(&run_dir)?;
+    Some((run_dir, state))
+}
@@ -0,0 +1,519 @@
+use serde::{Deserialize, Serialize};
+
+use super::parser::get_package_from_drv;
+
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Position {
+    pub file: String,
+    pub line: i64,
+    pub column: i64,
+}
+
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub struct TraceFrame {
+    pub description: String,
+    pub position: Option<Position>,
+}
+
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub enum NixError {
+    BuilderFailed {
+        package_name: String,
+        drv_path: String,
+        exit_code: Option<i64>,
+        log_tail: Vec<String>,
+        log_command: Option<String>,
+    },
+    HashMismatch {
+        package_name: String,
+        drv_path: String,
+        specified: String,
+        got: String,
+    },
+    DependencyFailed {
+        package_name: String,
+        drv_path: String,
+        failed_dependencies: Option<i64>,
+    },
+    NotDeterministic {
+        package_name: String,
+        drv_path: String,
+        output: String,
+        check_path: Option<String>,
+    },
+    Evaluation {
+        message: String,
+        position: Option<Position>,
+        trace: Vec<TraceFrame>,
+    },
+    Other(String),
+}
+
+/// Returns the text between the first pair of single quotes following `marker`.
+pub fn quoted_after<'a>(msg: &'a str, marker: &str) -> Option<&'a str> {
+    let (_, rest) = msg.split_once(marker)?;
+    let (_, rest) = rest.split_once('\'')?;
+    rest.split_once('\'').map(|(quoted, _)| quoted)
+}
+
+fn leading_number(s: &str) -> Option<i64> {
+    let digits: String = s
+        .trim_start()
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+/// Parses `at /path/file.nix:12:5:` into a position.
+fn parse_position(line: &str) -> Option<Position> {
+    let rest = line.trim().strip_prefix("at ")?;
+    let rest = rest.split(": ").next()?.trim_end_matches(':');
+    let mut parts = rest.rsplitn(3, ':');
+    let column = parts.next()?.parse().ok()?;
+    let line = parts.next()?.parse().ok()?;
+    let file = parts.next()?.to_owned();
+    Some(Position { file, line, column })
+}
+
+fn strip_error_prefix(line: &str) -> &str {
+    let line = line.trim();
+    line.strip_prefix("error:").unwrap_or(line).trim()
+}
+
+fn parse_evaluation(msg: &str) -> NixError {
+    let mut trace: Vec<TraceFrame> = Vec::new();
+    let mut message: Option<String> = None;
+    let mut position: Option<Position> = None;
+    for line in msg.lines() {
+        let trimmed = line.trim();
+        if let Some(description) = trimmed.strip_prefix('…') {
+            trace.push(TraceFrame {
+                description: description.trim().to_owned(),
+                position: None,
+            });
+        } else if trimmed.starts_with("at ") {
+            let pos = parse_position(trimmed);
+            match (&message, trace.last_mut()) {
+                (Some(_), _) => position = position.or(pos),
+                (None, Some(frame)) => frame.position = frame.position.take().or(pos),
+                (None, None) => position = position.or(pos),
+            }
+        } else if trimmed.starts_with("error:") && !strip_error_prefix(trimmed).is_empty() {
+            // with --show-trace the outer `error:` is empty and the real one comes last
+            message = Some(strip_error_prefix(trimmed).to_owned());
+        }
+    }
+    let message = message.unwrap_or_else(|| strip_error_prefix(msg).to_owned());
+    NixError::Evaluation {
+        message,
+        position,
+        trace,
+    }
+}
+
+pub fn parse_nix_error(msg: &str) -> NixError {
+    let first_line = msg.lines().next().unwrap_or_default();
+    if first_line.contains("hash mismatch in fixed-output derivation") {
+        let drv_path = quoted_after(first_line, "derivation")
+            .unwrap_or_default()
+            .to_owned();
+        let field = |name: &str| {
+            msg.lines()
+                .find_map(|l| l.trim().strip_prefix(name))
+                .map(|v| v.trim().to_owned())
+                .unwrap_or_default()
+        };
+        return NixError::HashMismatch {
+            package_name: get_package_from_drv(drv_path.clone()),
+            specified: field("specified:"),
+            got: field("got:"),
+            drv_path,
+        };
+    }
+    if let Some(drv_path) = quoted_after(first_line, "builder for") {
+        let exit_code = first_line
+            .split_once("exit code")
+            .and_then(|(_, code)| leading_number(code));
+        let log_tail = msg
+            .lines()
+            .filter_map(|l| l.trim_start().strip_prefix('>'))
+            .map(|l| l.strip_prefix(' ').unwrap_or(l).to_owned())
+            .collect();
+        let log_command = msg
+            .lines()
+            .find_map(|l| quoted_after(l, "For full logs, run"))
+            .map(|c| c.to_owned());
+        return NixError::BuilderFailed {
+            package_name: get_package_from_drv(drv_path.to_owned()),
+            drv_path: drv_path.to_owned(),
+            exit_code,
+            log_tail,
+            log_command,
+        };
+    }
+    if first_line.contains("may not be deterministic") {
+        let drv_path = quoted_after(first_line, "derivation")
+            .unwrap_or_default()
+            .to_owned();
+        return NixError::NotDeterministic {
+            package_name: get_package_from_drv(drv_path.clone()),
+            output: quoted_after(first_line, "deterministic: output")
+                .unwrap_or_default()
+                .to_owned(),
+            check_path: quoted_after(first_line, "differs from").map(|p| p.to_owned()),
+            drv_path,
+        };
+    }
+    // "1 dependencies of derivation '…' failed to build" (Nix < 2.19)
+    if first_line.contains("dependencies of derivation") {
+        let drv_path = quoted_after(first_line, "derivation")
+            .unwrap_or_default()
+            .to_owned();
+        return NixError::DependencyFailed {
+            package_name: get_package_from_drv(drv_path.clone()),
+            failed_dependencies: leading_number(strip_error_prefix(first_line)),
+            drv_path,
+        };
+    }
+    // "Cannot build '…'.\n Reason: 1 dependency failed." (Nix >= 2.19)
+    if let Some(drv_path) = quoted_after(first_line, "Cannot build") {
+        let reason = msg
+            .lines()
+            .find_map(|l| l.trim().strip_prefix("Reason:"))
+            .unwrap_or_default();
+        if !reason.contains("dependenc") {
+            return NixError::BuilderFailed {
+                package_name: get_package_from_drv(drv_path.to_owned()),
+                drv_path: drv_path.to_owned(),
+                exit_code: reason
+                    .split_once("exit code")
+                    .and_then(|(_, code)| leading_number(code)),
+                log_tail: Vec::new(),
+                log_command: None,
+            };
+        }
+        let failed_dependencies = leading_number(reason);
+        return NixError::DependencyFailed {
+            package_name: get_package_from_drv(drv_path.to_owned()),
+            drv_path: drv_path.to_owned(),
+            failed_dependencies,
+        };
+    }
+    if msg.contains('…') || msg.lines().any(|l| parse_position(l).is_some()) {
+        return parse_evaluation(msg);
+    }
+    NixError::Other(strip_error_prefix(msg).to_owned())
+}
+
+impl NixError {
+    /// The derivation the error is about, evaluation errors have none.
+    pub fn drv_path(&self) -> Option<&str> {
+        match self {
+            NixError::BuilderFailed { drv_path, .. }
+            | NixError::HashMismatch { drv_path, .. }
+            | NixError::DependencyFailed { drv_path, .. }
+            | NixError::NotDeterministic { drv_path, .. } => Some(drv_path),
+            NixError::Evaluation { .. } | NixError::Other(_) => None,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        match self {
+            NixError::BuilderFailed {
+                package_name,
+                exit_code,
+                log_command,
+                ..
+            } => format!(
+                "{}: builder failed{}{}",
+                package_name,
+                exit_code
+                    .map(|c| format!(" with exit code {}", c))
+                    .unwrap_or_default(),
+                log_command
+                    .as_ref()
+                    .map(|c| format!(" (see `{}`)", c))
+                    .unwrap_or_default()
+            ),
+            NixError::HashMismatch {
+                package_name,
+                specified,
+                got,
+                ..
+            } => format!(
+                "{}: hash mismatch, specified {} got {}",
+                package_name, specified, got
+            ),
+            NixError::DependencyFailed {
+                package_name,
+                failed_dependencies,
+                ..
+            } => format!(
+                "{}: {} dependencies failed",
+                package_name,
+                failed_dependencies
+                    .map(|n| n.to_string())
+                    .unwrap_or(String::from("some"))
+            ),
+            NixError::NotDeterministic {
+                package_name,
+                output,
+                ..
+            } => format!("{}: output {} is not deterministic", package_name, output),
+            NixError::Evaluation {
+                message, position, ..
+            } => match position {
+                Some(p) => format!("{}:{}:{}: {}", p.file, p.line, p.column, message),
+                None => message.to_owned(),
+            },
+            NixError::Other(msg) => msg.lines().next().unwrap_or_default().to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO_DRV: &str = "/nix/store/8bj9zs3ynqxwkdkfsb9ciw6ww1ny4ndk-hello-2.12.1.drv";
+
+    #[test]
+    fn builder_failed() {
+        let msg = format!(
+            "error: builder for '{}' failed with exit code 2;
+       last 3 log lines:
+       > make: *** [Makefile:1234: all] Error 2
+       > error: build failed
+       >
+       For full logs, run 'nix log {}'.",
+            HELLO_DRV, HELLO_DRV
+        );
+        assert_eq!(
+            parse_nix_error(&msg),
+            NixError::BuilderFailed {
+                package_name: "hello-2.12.1".to_owned(),
+                drv_path: HELLO_DRV.to_owned(),
+                exit_code: Some(2),
+                log_tail: vec![
+                    "make: *** [Makefile:1234: all] Error 2".to_owned(),
+                    "error: build failed".to_owned(),
+                    "".to_owned(),
+                ],
+                log_command: Some(format!("nix log {}", HELLO_DRV)),
+            }
+        );
+    }
+
+    #[test]
+    fn builder_failed_since_nix_2_19() {
+        let msg = format!(
+            "error: Cannot build '{}'.
+       Reason: builder failed with exit code 1.
+       Output paths:
+         /nix/store/26xbg1ndr7hbcncrlf9nhx5is2b25d13-hello-2.12.1",
+            HELLO_DRV
+        );
+        assert_eq!(
+            parse_nix_error(&msg),
+            NixError::BuilderFailed {
+                package_name: "hello-2.12.1".to_owned(),
+                drv_path: HELLO_DRV.to_owned(),
+                exit_code: Some(1),
+                log_tail: Vec::new(),
+                log_command: None,
+            }
+        );
+    }
+
+    #[test]
+    fn hash_mismatch() {
+        let drv_path = "/nix/store/y4yvs8k6mkyjg9jijjqbyh2cjgdryvp6-source.drv";
+        let msg = format!(
+            "error: hash mismatch in fixed-output derivation '{}':
+         specified: sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=
+            got:    sha256-ZL4fAhB0cYTR9qxqGJ2Bb3vzh4lxN05AMkexE5YZ+Zk=",
+            drv_path
+        );
+        assert_eq!(
+            parse_nix_error(&msg),
+            NixError::HashMismatch {
+                package_name: "source".to_owned(),
+                drv_path: drv_path.to_owned(),
+                specified: "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_owned(),
+                got: "sha256-ZL4fAhB0cYTR9qxqGJ2Bb3vzh4lxN05AMkexE5YZ+Zk=".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn dependency_failed() {
+        let drv_path = "/nix/store/m1r4wxbh4c4qq0yjy6lpchv0aj7gvh8l-app-1.0.drv";
+        let before_2_19 = format!(
+            "error: 1 dependencies of derivation '{}' failed to build",
+            drv_path
+        );
+        let since_2_19 = format!(
+            "error: Cannot build '{}'.
+       Reason: 1 dependency failed.
+       Output paths:
+         /nix/store/9z4vcl2z4xk5zpmzyq8l3v5x0a2a8j1c-app-1.0",
+            drv_path
+        );
+        for msg in [before_2_19, since_2_19] {
+            assert_eq!(
+                parse_nix_error(&msg),
+                NixError::DependencyFailed {
+                    package_name: "app-1.0".to_owned(),
+                    drv_path: drv_path.to_owned(),
+                    failed_dependencies: Some(1),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn not_deterministic() {
+        let msg = format!(
+            "error: derivation '{}' may not be deterministic: output '/nix/store/26xbg1ndr7hbcncrlf9nhx5is2b25d13-hello-2.12.1' differs from '/nix/store/26xbg1ndr7hbcncrlf9nhx5is2b25d13-hello-2.12.1.check'",
+            HELLO_DRV
+        );
+        assert_eq!(
+            parse_nix_error(&msg),
+            NixError::NotDeterministic {
+                package_name: "hello-2.12.1".to_owned(),
+                drv_path: HELLO_DRV.to_owned(),
+                output: "/nix/store/26xbg1ndr7hbcncrlf9nhx5is2b25d13-hello-2.12.1".to_owned(),
+                check_path: Some(
+                    "/nix/store/26xbg1ndr7hbcncrlf9nhx5is2b25d13-hello-2.12.1.check".to_owned()
+                ),
+            }
+        );
+    }
+
+    #[test]
+    fn evaluation_with_trace() {
+        let msg = "error:
+       … while calling the 'derivationStrict' builtin
+
+         at //builtin/derivation.nix:9:12: (source not available)
+
+       … while evaluating attribute 'buildInputs' of derivation 'foo'
+
+         at /home/u/proj/flake.nix:12:9:
+
+           11|         name = \"foo\";
+           12|         buildInputs = [ bar ];
+             |         ^
+
+       error: undefined variable 'bar'
+
+         at /home/u/proj/flake.nix:12:25:
+
+           11|         name = \"foo\";
+           12|         buildInputs = [ bar ];
+             |                         ^";
+        assert_eq!(
+            parse_nix_error(msg),
+            NixError::Evaluation {
+                message: "undefined variable 'bar'".to_owned(),
+                position: Some(Position {
+                    file: "/home/u/proj/flake.nix".to_owned(),
+                    line: 12,
+                    column: 25,
+                }),
+                trace: vec![
+                    TraceFrame {
+                        description: "while calling the 'derivationStrict' builtin".to_owned(),
+                        position: Some(Position {
+                            file: "//builtin/derivation.nix".to_owned(),
+                            line: 9,
+                            column: 12,
+                        }),
+                    },
+                    TraceFrame {
+                        description: "while evaluating attribute 'buildInputs' of derivation 'foo'"
+                            .to_owned(),
+                        position: Some(Position {
+                            file: "/home/u/proj/flake.nix".to_owned(),
+                            line: 12,
+                            column: 9,
+                        }),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn evaluation_without_trace() {
+        let msg = "error: attribute 'hello' missing
+
+       at /home/u/proj/flake.nix:7:5:
+
+            6|   {
+            7|     packages.x86_64-linux.default = pkgs.hello;
+             |     ^";
+        assert_eq!(
+            parse_nix_error(msg),
+            NixError::Evaluation {
+                message: "attribute 'hello' missing".to_owned(),
+                position: Some(Position {
+                    file: "/home/u/proj/flake.nix".to_owned(),
+                    line: 7,
+                    column: 5,
+                }),
+                trace: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn other() {
+        let msg =
+            "error: path '/nix/store/26xbg1ndr7hbcncrlf9nhx5is2b25d13-hello-2.12.1' is not valid";
+        assert_eq!(
+            parse_nix_error(msg),
+            NixError::Other(
+                "path '/nix/store/26xbg1ndr7hbcncrlf9nhx5is2b25d13-hello-2.12.1' is not valid"
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn quoted_after_marker() {
+        let line = "error: builder for '/nix/store/a-b.drv' failed, see 'nix log'";
+        assert_eq!(
+            quoted_after(line, "builder for"),
+            Some("/nix/store/a-b.drv")
+        );
+        assert_eq!(quoted_after(line, "see"), Some("nix log"));
+        assert_eq!(quoted_after(line, "missing"), None);
+        assert_eq!(quoted_after("derivation 'unterminated", "derivation"), None);
+    }
+
+    #[test]
+    fn positions() {
+        assert_eq!(
+            parse_position("  at /home/u/proj/flake.nix:12:25:"),
+            Some(Position {
+                file: "/home/u/proj/flake.nix".to_owned(),
+                line: 12,
+                column: 25,
+            })
+        );
+        assert_eq!(
+            parse_position(
+                "at «github:NixOS/nixpkgs/63dacb4»/lib/modules.nix:236:24: (source not available)"
+            ),
+            Some(Position {
+                file: "«github:NixOS/nixpkgs/63dacb4»/lib/modules.nix".to_owned(),
+                line: 236,
+                column: 24,
+            })
+        );
+        assert_eq!(parse_position("at the end"), None);
+        assert_eq!(parse_position("12| foo"), None);
+    }
+}
@@ -1,13 +1,45 @@
-use crate::nix_tracker::types::CommandState;
+use super::errors::NixError;
+use crate::nix_history::runs::write_run_state;
+use crate::nix_tracker::{
+    hash_mismatch::print_hash_fix,
+    types::{CommandState, JSONCommandState},
+};
 use chrono::Utc;
 use std::{
-    env,
-    fs::{File, OpenOptions},
+    cell::RefCell,
+    env, fmt,
+    fs::File,
     io::{self, Write},
     path::Path,
+    sync::atomic::{AtomicBool, Ordering},
 };
 use yansi::{Paint, Painted};
 
+static HUMAN_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static LOG_PREFIX: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Prefixes the log lines written from the current thread, to tell concurrent runs apart.
+pub fn set_log_prefix(prefix: &str) {
+    LOG_PREFIX.with(|p| *p.borrow_mut() = Some(prefix.to_owned()));
+}
+
+/// Moves the human readable output to stderr, leaving stdout to a machine readable format.
+pub fn human_output_to_stderr() {
+    HUMAN_TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+pub fn print_human(line: impl fmt::Display) {
+    if HUMAN_TO_STDERR.load(Ordering::Relaxed) {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+        io::stdout().flush().unwrap();
+    }
+}
+
 pub fn filter_ansi(mut utf8_string: String) -> Painted<std::string::String> {
     let filter_from_string = [
         "\\u001b[39m",
@@ -583,85 +615,120 @@ pub fn filter_ansi(mut utf8_string: String) -> Painted<std::string::String> {
     Painted::new(utf8_string)
 }
 
-pub fn append_log_to_file(file_name: String, msg: String) {
-    let append = match env::var("DUMP_LOGS") {
-        Ok(value) => value.parse().unwrap_or_default(),
-        Err(_) => false,
-    };
-    if append {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .append(true)
-            .create(true)
-            .open(file_name + ".log")
-            .unwrap();
-
-        if let Err(e) = writeln!(file, "{}", msg) {
-            eprintln!("Couldn't write to file: {}", e);
+pub fn print_error_report(errors: &[NixError]) {
+    if errors.is_empty() {
+        return;
+    }
+    print_human(format!("{} error(s) reported by nix:", errors.len()));
+    for err in errors {
+        log::error!("  {}", err.summary());
+        if let NixError::HashMismatch {
+            drv_path,
+            specified,
+            got,
+            ..
+        } = err
+        {
+            print_hash_fix(drv_path, specified, got);
+        }
+        if let NixError::Evaluation { trace, .. } = err {
+            for frame in trace {
+                match &frame.position {
+                    Some(p) => log::error!(
+                        "    … {} at {}:{}:{}",
+                        frame.description,
+                        p.file,
+                        p.line,
+                        p.column
+                    ),
+                    None => log::error!("    … {}", frame.description),
+                }
+            }
         }
     }
 }
 
-pub fn dump_state_to_file(state: CommandState) {
-    println!(
+pub fn dump_state_to_file(state: CommandState) -> JSONCommandState {
+    print_error_report(&state.errors);
+    print_human(format!(
         "time taken to run the command: {:?}",
         state
             .end
             .unwrap()
             .duration_since(state.start)
             .expect("Clock may have gone backwards")
-    );
+    ));
     let t = Utc::now().to_rfc3339().to_string();
     let mut file = match Path::new("command_state.json").exists() {
         true => File::create("command_state".to_owned() + "_" + &t + ".json").unwrap(),
         false => File::create("command_state.json").unwrap(),
     };
-    let json_dump = serde_json::to_string_pretty(&CommandState::to_json(state)).unwrap();
+    let run_dir = state.run_dir.clone();
+    let json_state = CommandState::to_json(state);
+    if let Some(eval) = &json_state.eval {
+        eval.print();
+    }
+    if let Some(dispositions) = &json_state.dispositions {
+        dispositions.print();
+    }
+    if let Some(closure) = &json_state.closure {
+        closure.print();
+    }
+    let json_dump = serde_json::to_string_pretty(&json_state).unwrap();
     let _ = file.write_all(json_dump.as_bytes());
+    if let Some(run_dir) = run_dir {
+        match write_run_state(&run_dir, &json_state) {
+            Ok(()) => print_human(format!("run stored in {}", run_dir.display())),
+            Err(err) => log::warn!("unable to store the run in {}: {}", run_dir.display(), err),
+        }
+    }
+    json_state
 }
 
 pub fn log_(record: &log::Record<'_>) {
-    let str = record.args().to_string();
-    let ansi = match env::var("ANSI").unwrap_or("true".to_owned()).as_str() {
-        "false" => false,
-        _ => true,
+    let str = match LOG_PREFIX.with(|p| p.borrow().clone()) {
+        Some(prefix) => format!("[{}] {}", prefix, record.args()),
+        None => record.args().to_string(),
     };
+    let ansi = !matches!(
+        env::var("ANSI").unwrap_or("true".to_owned()).as_str(),
+        "false"
+    );
     match record.level() {
         log::Level::Error => {
             if ansi {
-                println!("{}", Paint::red(&filter_ansi(str)));
+                print_human(Paint::red(&filter_ansi(str)));
             } else {
-                println!("[Error]{}", filter_ansi(str))
+                print_human(format!("[Error]{}", filter_ansi(str)))
             }
         }
         log::Level::Warn => {
             if ansi {
-                println!("{}", Paint::magenta(&filter_ansi(str)));
+                print_human(Paint::magenta(&filter_ansi(str)));
             } else {
-                println!("[Warn] {}", filter_ansi(str))
+                print_human(format!("[Warn] {}", filter_ansi(str)))
             }
         }
         log::Level::Info => {
             if ansi {
-                println!("{}", Paint::white(&filter_ansi(str)));
+                print_human(Paint::white(&filter_ansi(str)));
             } else {
-                println!("[Info] {}", filter_ansi(str))
+                print_human(format!("[Info] {}", filter_ansi(str)))
             }
         }
         log::Level::Debug => {
             if ansi {
-                println!("{}", Paint::bright_yellow(&filter_ansi(str)));
+                print_human(Paint::bright_yellow(&filter_ansi(str)));
             } else {
-                println!("[Debug]{}", filter_ansi(str))
+                print_human(format!("[Debug]{}", filter_ansi(str)))
             }
         }
         log::Level::Trace => {
             if ansi {
-                println!("{}", Paint::blue(&filter_ansi(str)));
+                print_human(Paint::blue(&filter_ansi(str)));
             } else {
-                println!("[Trace]{}", filter_ansi(str))
+                print_human(format!("[Trace]{}", filter_ansi(str)))
             }
         }
     }
-    io::stdout().flush().unwrap();
 }
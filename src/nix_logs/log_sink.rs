@@ -0,0 +1,166 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+use super::types::{ActivityResult, JSONMessage};
+
+/// Files kept open at once, the least recently written one is closed beyond that.
+const MAX_OPEN_FILES: usize = 128;
+
+enum LogOp {
+    Append(PathBuf, String),
+    /// the activity writing to the file stopped
+    Close(PathBuf),
+}
+
+/// Handle used to queue lines for the writer thread of a [`LogSink`].
+#[derive(Clone)]
+pub struct LogSender {
+    sender: Sender<LogOp>,
+}
+
+impl LogSender {
+    pub fn append(&self, file: PathBuf, line: String) {
+        let _ = self.sender.send(LogOp::Append(file, line));
+    }
+
+    /// Flushes and closes `file` once the lines queued before are written.
+    pub fn close(&self, file: PathBuf) {
+        let _ = self.sender.send(LogOp::Close(file));
+    }
+}
+
+/// Writes every log line on a single thread, in the order they were queued,
+/// keeping a buffered handle per file until its activity stops.
+pub struct LogSink {
+    sender: LogSender,
+    writer: JoinHandle<usize>,
+    dump_activity_logs: bool,
+}
+
+/// Returns how many files could not be opened or written to.
+fn write_lines(receiver: Receiver<LogOp>) -> usize {
+    // each writer with the last time it was used, to close the oldest one
+    let mut files: HashMap<PathBuf, (BufWriter<File>, u64)> = HashMap::new();
+    let mut failures = 0;
+    for (n, op) in receiver.into_iter().enumerate() {
+        let (path, line) = match op {
+            LogOp::Append(path, line) => (path, line),
+            LogOp::Close(path) => {
+                if let Some((mut writer, _)) = files.remove(&path) {
+                    let _ = writer.flush();
+                }
+                continue;
+            }
+        };
+        if !files.contains_key(&path) {
+            if files.len() >= MAX_OPEN_FILES {
+                let oldest = files
+                    .iter()
+                    .min_by_key(|(_, (_, used))| *used)
+                    .map(|(path, _)| path.clone());
+                if let Some((mut writer, _)) = oldest.and_then(|oldest| files.remove(&oldest)) {
+                    let _ = writer.flush();
+                }
+            }
+            match OpenOptions::new().append(true).create(true).open(&path) {
+                Ok(file) => {
+                    files.insert(path.clone(), (BufWriter::new(file), 0));
+                }
+                Err(e) => {
+                    log::warn!("unable to open {}: {}", path.display(), e);
+                    failures += 1;
+                    continue;
+                }
+            }
+        }
+        if let Some((writer, used)) = files.get_mut(&path) {
+            *used = n as u64;
+            if let Err(e) = writeln!(writer, "{}", line) {
+                log::warn!("unable to write to {}: {}", path.display(), e);
+                failures += 1;
+            }
+        }
+    }
+    for (_, (mut writer, _)) in files {
+        let _ = writer.flush();
+    }
+    failures
+}
+
+impl Default for LogSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The text `id_<n>.log` gets for a message, progress and other results are left out.
+pub fn activity_log_text(msg: &Option<JSONMessage>) -> Option<String> {
+    match msg {
+        Some(JSONMessage::Start(start)) => Some(start.text.to_owned()),
+        Some(JSONMessage::Stop(_)) => Some(String::from("done")),
+        Some(JSONMessage::Result(res)) => match &res.result {
+            ActivityResult::BuildLogLine(msg)
+            | ActivityResult::UntrustedPath(msg)
+            | ActivityResult::CorruptedPath(msg)
+            | ActivityResult::SetPhase(msg) => Some(msg.to_owned()),
+            _ => None,
+        },
+        Some(JSONMessage::Message(msg)) => Some(msg.msg.to_owned()),
+        None => None,
+    }
+}
+
+impl LogSink {
+    pub fn new() -> LogSink {
+        let (sender, receiver) = channel();
+        let writer = thread::spawn(move || write_lines(receiver));
+        let dump_activity_logs = match env::var("DUMP_LOGS") {
+            Ok(value) => value.parse().unwrap_or_default(),
+            Err(_) => false,
+        };
+        LogSink {
+            sender: LogSender { sender },
+            writer,
+            dump_activity_logs,
+        }
+    }
+
+    pub fn sender(&self) -> LogSender {
+        self.sender.clone()
+    }
+
+    /// Queues the text of a message for `id_<n>.log` when ENV `DUMP_LOGS=true`.
+    pub fn record(&self, id: i64, msg: &Option<JSONMessage>) {
+        if !self.dump_activity_logs {
+            return;
+        }
+        let text = activity_log_text(msg);
+        let file = PathBuf::from(format!("id_{}.log", id));
+        if let Some(text) = text {
+            self.sender.append(file.clone(), text);
+        }
+        if let Some(JSONMessage::Stop(_)) = msg {
+            self.sender.close(file);
+        }
+    }
+
+    /// Waits for every queued line to be written and flushes all files, returns how many
+    /// files could not be opened or written to.
+    pub fn finish(self) -> usize {
+        drop(self.sender);
+        match self.writer.join() {
+            Ok(failures) => failures,
+            Err(_) => {
+                log::warn!("log writer thread panicked");
+                1
+            }
+        }
+    }
+}
@@ -1,4 +1,6 @@
+pub mod errors;
 pub mod helpers;
+pub mod log_sink;
 pub mod parser;
 pub mod process_logs;
 pub mod types;
@@ -1,9 +1,12 @@
-use std::thread;
+use std::{borrow::Cow, fmt, marker::PhantomData};
 
-use super::{helpers::append_log_to_file, types::*};
-use serde_json::{self, Value};
+use super::types::*;
+use serde::{
+    de::{self, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
 
-fn get_package_from_drv(store_path: String) -> String {
+pub(crate) fn get_package_from_drv(store_path: String) -> String {
     match store_path.split_once('-') {
         Some((_, xs)) => xs
             .to_owned()
@@ -48,224 +51,266 @@ fn number_to_activity_type(number: i64) -> ActivityType {
     }
 }
 
-fn str_to_activity_result(activity_result: i64, fields: Value) -> ActivityResult {
-    match activity_result {
-        100 => {
-            let fields_i64: Vec<i64> = serde_json::from_value(fields).unwrap();
-            ActivityResult::FileLinked(fields_i64[0], fields_i64[1])
-        }
-        101 => {
-            let fields_str: Vec<String> = serde_json::from_value(fields).unwrap();
-            ActivityResult::BuildLogLine(fields_str[0].to_owned())
-        }
-        102 => {
-            let fields_str: Vec<String> = serde_json::from_value(fields).unwrap();
-            ActivityResult::UntrustedPath(fields_str[0].to_owned())
-        }
-        103 => {
-            let fields_str: Vec<String> = serde_json::from_value(fields).unwrap();
-            ActivityResult::CorruptedPath(fields_str[0].to_owned())
-        }
-        104 => {
-            let fields_str: Vec<String> = serde_json::from_value(fields).unwrap();
-            ActivityResult::SetPhase(fields_str[0].to_owned())
-        }
-        105 => {
-            let fields_i64: Vec<i64> = serde_json::from_value(fields).unwrap();
-            ActivityResult::Progress(ActivityProgress {
-                done: fields_i64[0],
-                expected: fields_i64[1],
-                running: fields_i64[2],
-                failed: fields_i64[3],
-            })
-        }
-        106 => {
-            let fields_i64: Vec<i64> = serde_json::from_value(fields).unwrap();
-            ActivityResult::SetExpected(number_to_activity_type(fields_i64[0]), fields_i64[1])
-        }
-        107 => {
-            let fields_str: Vec<String> = serde_json::from_value(fields).unwrap();
-            ActivityResult::PostBuildLogLine(fields_str[0].to_owned())
+/// A value of the `fields` array, borrowed from the line whenever it has no escapes.
+#[derive(Debug)]
+enum Field<'a> {
+    Int(i64),
+    Str(Cow<'a, str>),
+    Other,
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Field<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FieldVisitor<'a>(PhantomData<&'a ()>);
+
+        impl<'de: 'a, 'a> Visitor<'de> for FieldVisitor<'a> {
+            type Value = Field<'a>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string or an integer")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(Field::Int(v))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(Field::Int(v as i64))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+                Ok(Field::Int(v as i64))
+            }
+
+            fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+                Ok(Field::Str(Cow::Borrowed(v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(Field::Str(Cow::Owned(v.to_owned())))
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+                Ok(Field::Str(Cow::Owned(v)))
+            }
+
+            fn visit_bool<E: de::Error>(self, _: bool) -> Result<Self::Value, E> {
+                Ok(Field::Other)
+            }
+
+            fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+                Ok(Field::Other)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                while seq.next_element::<IgnoredAny>()?.is_some() {}
+                Ok(Field::Other)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+                Ok(Field::Other)
+            }
         }
-        x => panic!("unable to parse str_to_activity_result: {}", x),
+
+        deserializer.deserialize_any(FieldVisitor(PhantomData))
     }
 }
 
-fn str_to_activity(activity: i64, fields: Value) -> Activity {
-    match activity {
-        // actUnknown = 0,
-        0 => Activity::ActUnknown,
-        // actCopyPath = 100,
-        100 => {
-            let fields_str: Vec<String> = serde_json::from_value(fields).unwrap();
-            let store_path = fields_str[0].to_owned();
-            let from = fields_str[1].to_owned();
-            let to = fields_str[2].to_owned();
-            let package_name = get_package_from_drv(store_path.to_owned());
-            Activity::ActCopyPath(package_name, store_path, from, to)
-        }
-        // actFileTransfer = 101,
-        101 => {
-            let fields_str: Vec<String> = serde_json::from_value(fields).unwrap();
-            let nar = fields_str[0].to_owned();
-            Activity::ActFileTransfer(nar)
-        }
-        // actRealise = 102,
-        102 => Activity::ActRealise,
-        // actCopyPaths = 103,
-        103 => Activity::ActCopyPaths,
-        // actBuilds = 104,
-        104 => Activity::ActBuilds,
-        // actBuild = 105,
-        105 => {
-            let fields_str: Vec<Value> = serde_json::from_value(fields).unwrap();
-            let path: String = serde_json::from_value(fields_str[0].to_owned()).unwrap();
-            let host: String = serde_json::from_value(fields_str[1].to_owned()).unwrap();
-            let package_name = get_package_from_drv(path.clone());
-            Activity::ActBuild(package_name, path, host, 1, 1)
-        }
-        // actOptimiseStore = 106,
-        106 => Activity::ActOptimiseStore,
-        // actVerifyPaths = 107,
-        107 => Activity::ActVerifyPaths,
-        // actSubstitute = 108,
-        108 => {
-            let fields_str: Vec<String> = serde_json::from_value(fields).unwrap();
-            let path = fields_str[0].to_owned();
-            let host = fields_str[1].to_owned();
-            let package_name = get_package_from_drv(path.clone());
-            Activity::ActSubstitute(package_name, path, host)
-        }
-        // actQueryPathInfo = 109,
-        109 => {
-            let fields_str: Vec<String> = serde_json::from_value(fields).unwrap();
-            let path = fields_str[0].to_owned();
-            let host = fields_str[1].to_owned();
-            let package_name = get_package_from_drv(path.clone());
-            Activity::ActQueryPathInfo(package_name, path, host)
+/// One `@nix` line of `--log-format internal-json`, every key any action may carry. It borrows
+/// from the line, only the strings a [`JSONMessage`] keeps are copied out of it.
+#[derive(Debug, Deserialize)]
+struct RawMessage<'a> {
+    #[serde(borrow)]
+    action: Cow<'a, str>,
+    #[serde(default = "no_id")]
+    id: i64,
+    #[serde(default)]
+    level: i64,
+    #[serde(default)]
+    parent: i64,
+    #[serde(rename = "type", default)]
+    kind: i64,
+    #[serde(borrow, default)]
+    text: Cow<'a, str>,
+    #[serde(borrow, default)]
+    msg: Cow<'a, str>,
+    #[serde(borrow, default)]
+    fields: Vec<Field<'a>>,
+}
+
+fn no_id() -> i64 {
+    -1
+}
+
+impl RawMessage<'_> {
+    fn str(&self, i: usize) -> String {
+        match self.fields.get(i) {
+            Some(Field::Str(s)) => s.to_string(),
+            Some(Field::Int(n)) => n.to_string(),
+            _ => String::new(),
         }
-        // actPostBuildHook = 110,
-        110 => {
-            let fields_str: Vec<String> = serde_json::from_value(fields).unwrap();
-            Activity::ActPostBuildHook(fields_str[0].to_owned())
+    }
+
+    fn int(&self, i: usize) -> i64 {
+        match self.fields.get(i) {
+            Some(Field::Int(n)) => *n,
+            Some(Field::Str(s)) => s.parse().unwrap_or_default(),
+            _ => 0,
         }
-        // actBuildWaiting = 111,
-        111 => Activity::ActBuildWaiting,
-        // actFetchTree = 112,
-        112 => {
-            Activity::ActFetchTree
+    }
+
+    fn activity_result(&self) -> Option<ActivityResult> {
+        let result = match self.kind {
+            100 => ActivityResult::FileLinked(self.int(0), self.int(1)),
+            101 => ActivityResult::BuildLogLine(self.str(0)),
+            102 => ActivityResult::UntrustedPath(self.str(0)),
+            103 => ActivityResult::CorruptedPath(self.str(0)),
+            104 => ActivityResult::SetPhase(self.str(0)),
+            105 => ActivityResult::Progress(ActivityProgress {
+                done: self.int(0),
+                expected: self.int(1),
+                running: self.int(2),
+                failed: self.int(3),
+            }),
+            106 => ActivityResult::SetExpected(number_to_activity_type(self.int(0)), self.int(1)),
+            107 => ActivityResult::PostBuildLogLine(self.str(0)),
+            x => {
+                log::debug!("unable to parse activity result: {}", x);
+                return None;
+            }
+        };
+        Some(result)
+    }
+
+    fn activity(&self) -> Activity {
+        match self.kind {
+            100 => {
+                let store_path = self.str(0);
+                let package_name = get_package_from_drv(store_path.to_owned());
+                Activity::ActCopyPath(package_name, store_path, self.str(1), self.str(2))
+            }
+            101 => Activity::ActFileTransfer(self.str(0)),
+            102 => Activity::ActRealise,
+            103 => Activity::ActCopyPaths,
+            104 => Activity::ActBuilds,
+            105 => {
+                let path = self.str(0);
+                let package_name = get_package_from_drv(path.clone());
+                Activity::ActBuild(package_name, path, self.str(1), 1, 1)
+            }
+            106 => Activity::ActOptimiseStore,
+            107 => Activity::ActVerifyPaths,
+            108 => {
+                let path = self.str(0);
+                let package_name = get_package_from_drv(path.clone());
+                Activity::ActSubstitute(package_name, path, self.str(1))
+            }
+            109 => {
+                let path = self.str(0);
+                let package_name = get_package_from_drv(path.clone());
+                Activity::ActQueryPathInfo(package_name, path, self.str(1))
+            }
+            110 => Activity::ActPostBuildHook(self.str(0)),
+            111 => Activity::ActBuildWaiting,
+            112 => Activity::ActFetchTree,
+            _ => Activity::ActUnknown,
         }
-        _ => panic!("Invalid Activity"),
     }
 }
 
-pub fn parse(line: String) -> (Option<JSONMessage>, i64) {
-    let opt_res: Result<serde_json::Value, _> = serde_json::from_str(&line.replace("@nix ", ""));
-    match opt_res {
-        Ok(res) => {
-            let action = res.get("action").unwrap().as_str();
-            let d_fields = Value::Array([].to_vec());
-            let mut id = -1;
-            let msg = match action {
-                Some("start") => {
-                    id = serde_json::from_value(res.get("id").unwrap().to_owned()).unwrap();
-                    let fields = match res.get("fields") {
-                        Some(v) => v,
-                        None => &d_fields,
-                    };
-                    let level = str_to_verbosity(
-                        serde_json::from_value(res.get("level").unwrap().to_owned()).unwrap(),
-                    );
-                    let text: String =
-                        serde_json::from_value(res.get("text").unwrap().to_owned()).unwrap();
-                    let text_log: String = text.clone();
-                    let _type =
-                        serde_json::from_value(res.get("type").unwrap().to_owned()).unwrap();
-                    let activity = str_to_activity(_type, fields.clone());
-
-                    //write to log file
-                    thread::spawn(move || {
-                        let log_file = "id_".to_owned() + &(id.clone().to_string());
-                        append_log_to_file(log_file, text_log.to_owned());
-                    });
+/// Parses one line without an intermediate `serde_json::Value`, the returned message owns its
+/// strings so it can outlive the line.
+pub fn parse(line: &str) -> (Option<JSONMessage>, i64) {
+    let json = line.strip_prefix("@nix ").unwrap_or(line);
+    let res: RawMessage = match serde_json::from_str(json) {
+        Ok(res) => res,
+        Err(err) => {
+            log::error!("Failed to parse: {} -> {}", line, err);
+            return (None, -1);
+        }
+    };
+    let msg = match res.action.as_ref() {
+        "start" => Some(JSONMessage::Start(StartAction {
+            id: res.id,
+            parent: res.parent,
+            level: str_to_verbosity(res.level),
+            activity: res.activity(),
+            text: res.text.to_string(),
+        })),
+        "stop" => Some(JSONMessage::Stop(StopAction { id: res.id })),
+        "result" => res
+            .activity_result()
+            .map(|result| JSONMessage::Result(ResultAction { id: res.id, result })),
+        "msg" => Some(JSONMessage::Message(MessageAction {
+            level: str_to_verbosity(res.level),
+            msg: res.msg.to_string(),
+        })),
+        l => {
+            log::debug!("Missed to handle: {:#?} , json: {:#?}", l, json);
+            None
+        }
+    };
+    (msg, res.id)
+}
 
-                    Some(JSONMessage::Start(StartAction {
-                        id,
-                        level,
-                        activity,
-                        text: text.to_owned(),
-                    }))
-                }
-                Some("stop") => {
-                    id = serde_json::from_value(res.get("id").unwrap().to_owned()).unwrap();
-                    thread::spawn(move || {
-                        let log_file = "id_".to_owned() + &(id.clone().to_string());
-                        append_log_to_file(log_file, String::from("done"));
-                    });
-                    Some(JSONMessage::Stop(StopAction { id }))
-                }
-                Some("result") => {
-                    id = serde_json::from_value(res.get("id").unwrap().to_owned()).unwrap();
-                    let fields = res.get("fields").unwrap();
-                    let activity: ActivityResult = str_to_activity_result(
-                        serde_json::from_value(res.get("type").unwrap().to_owned()).unwrap(),
-                        fields.clone(),
-                    );
-                    let text: Option<String> = match activity.clone() {
-                        ActivityResult::BuildLogLine(msg) => Some(msg),
-                        ActivityResult::UntrustedPath(msg) => Some(msg),
-                        ActivityResult::CorruptedPath(msg) => Some(msg),
-                        ActivityResult::SetPhase(msg) => Some(msg),
-                        _ => None, // ActivityResult::Progress(msg) => Some(),
-                                   // ActivityResult::FileLinked(_, _) => Some(String::from("")),
-                                   // ActivityResult::SetExpected(_, _) => Some(String::from("")),
-                                   // ActivityResult::PostBuildLogLine(_) => Some(String::from("")),
-                    };
-                    match text {
-                        Some(msg) => {
-                            thread::spawn(move || {
-                                let log_file = "id_".to_owned() + &(id.clone().to_string());
-                                append_log_to_file(log_file, msg.clone());
-                            });
-                        }
-                        None => (),
-                    }
-                    Some(JSONMessage::Result(ResultAction {
-                        id,
-                        result: activity,
-                    }))
-                }
-                Some("msg") => {
-                    let level = str_to_verbosity(
-                        serde_json::from_value(res.get("level").unwrap().to_owned()).unwrap(),
-                    );
-                    let msg: String =
-                        serde_json::from_value(res.get("msg").unwrap().clone()).unwrap();
-                    let msg_log: String =
-                        serde_json::from_value(res.get("msg").unwrap().clone()).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                    //write to log file
-                    thread::spawn(move || {
-                        let log_file = "id_".to_owned() + &(id.clone().to_string());
-                        append_log_to_file(log_file, msg_log.to_owned());
-                    });
+    #[test]
+    fn build_start() {
+        let line = r#"@nix {"action":"start","id":42,"level":3,"parent":7,"text":"building '/nix/store/8bj9zs3ynqxwkdkfsb9ciw6ww1ny4ndk-hello-2.12.1.drv'","type":105,"fields":["/nix/store/8bj9zs3ynqxwkdkfsb9ciw6ww1ny4ndk-hello-2.12.1.drv","",1,1]}"#;
+        let (msg, id) = parse(line);
+        assert_eq!(id, 42);
+        match msg {
+            Some(JSONMessage::Start(start)) => {
+                assert_eq!(start.parent, 7);
+                assert_eq!(
+                    start.activity,
+                    Activity::ActBuild(
+                        "hello-2.12.1".to_owned(),
+                        "/nix/store/8bj9zs3ynqxwkdkfsb9ciw6ww1ny4ndk-hello-2.12.1.drv".to_owned(),
+                        "".to_owned(),
+                        1,
+                        1
+                    )
+                );
+            }
+            other => panic!("expected a start action, got {:?}", other),
+        }
+    }
 
-                    Some(JSONMessage::Message(MessageAction {
-                        level,
-                        msg: msg.to_owned(),
-                    }))
-                }
-                Some(l) => {
-                    println!("Missed to handle: {:#?} , json: {:#?}", l, res);
-                    None
-                }
-                None => None,
-            };
-            (msg, id)
+    #[test]
+    fn escaped_log_line() {
+        let line =
+            r#"@nix {"action":"result","id":42,"type":101,"fields":["checking \"foo\"\tok"]}"#;
+        match parse(line).0 {
+            Some(JSONMessage::Result(res)) => assert_eq!(
+                res.result,
+                ActivityResult::BuildLogLine("checking \"foo\"\tok".to_owned())
+            ),
+            other => panic!("expected a result action, got {:?}", other),
         }
-        Err(err) => {
-            log::error!("Failed to parse: {} -> {}", line, err);
-            (None, -1)
+    }
+
+    #[test]
+    fn nested_fields_are_skipped() {
+        let line = r#"@nix {"action":"result","id":3,"type":104,"fields":["buildPhase",["a",{"b":[1,2]}],{"c":null}]}"#;
+        match parse(line).0 {
+            Some(JSONMessage::Result(res)) => {
+                assert_eq!(
+                    res.result,
+                    ActivityResult::SetPhase("buildPhase".to_owned())
+                )
+            }
+            other => panic!("expected a result action, got {:?}", other),
         }
     }
+
+    #[test]
+    fn invalid_line() {
+        assert_eq!(parse("not json").1, -1);
+        assert!(parse("not json").0.is_none());
+    }
 }
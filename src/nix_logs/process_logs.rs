@@ -1,8 +1,15 @@
 use yansi::Paint;
 
-use crate::nix_tracker::types::{ActivityState, CommandState};
+use crate::nix_tracker::{
+    eta::planned_builds,
+    eval::is_realise_activity,
+    types::{ActivityState, CommandState},
+};
 
-use super::types::{JSONMessage, Verbosity};
+use super::{
+    errors::parse_nix_error,
+    types::{JSONMessage, Verbosity},
+};
 use std::time::SystemTime;
 
 pub fn process_log(
@@ -12,7 +19,14 @@ pub fn process_log(
 ) -> &mut CommandState {
     match opt_msg {
         Some(JSONMessage::Start(msg)) => {
-            let (id, _level, _text, activity) = (msg.id, msg.level, msg.text, msg.activity);
+            if state.first_realise.is_none() && is_realise_activity(&msg.activity) {
+                state.first_realise = Some(SystemTime::now());
+            }
+            let (id, _level, text, activity) = (msg.id, msg.level, msg.text, msg.activity);
+            let parent = match msg.parent {
+                0 => None,
+                parent => Some(parent),
+            };
             match activity {
                 super::types::Activity::ActCopyPath(package_name, store_path, from, to) => {
                     let now = SystemTime::now();
@@ -34,6 +48,9 @@ pub fn process_log(
                         phase: None,
                         progress: None,
                         package_name: Some(package_name),
+                        text: text.clone(),
+                        phases: Vec::new(),
+                        parent,
                     };
                     state.activity.insert(id, new_activity_state);
                     state.required_derivations.insert(store_path.clone());
@@ -53,6 +70,9 @@ pub fn process_log(
                         phase: None,
                         progress: None,
                         package_name: None,
+                        text: text.clone(),
+                        phases: Vec::new(),
+                        parent,
                     };
                     state.activity.insert(id, new_activity_state);
                 }
@@ -75,6 +95,9 @@ pub fn process_log(
                         phase: None,
                         progress: None,
                         package_name: Some(package_name),
+                        text: text.clone(),
+                        phases: Vec::new(),
+                        parent,
                     };
                     state.activity.insert(id, new_activity_state);
                     state.required_derivations.insert(store_path.clone());
@@ -88,7 +111,7 @@ pub fn process_log(
                         now
                     );
                     let new_activity_state = ActivityState {
-                        activity: super::types::Activity::ActSubstitute(
+                        activity: super::types::Activity::ActQueryPathInfo(
                             package_name.clone(),
                             store_path.clone(),
                             from,
@@ -98,6 +121,9 @@ pub fn process_log(
                         phase: None,
                         progress: None,
                         package_name: Some(package_name),
+                        text: text.clone(),
+                        phases: Vec::new(),
+                        parent,
                     };
                     state.activity.insert(id, new_activity_state);
                     state.required_derivations.insert(store_path.clone());
@@ -117,6 +143,9 @@ pub fn process_log(
                         phase: None,
                         progress: None,
                         package_name: None,
+                        text: text.clone(),
+                        phases: Vec::new(),
+                        parent,
                     };
                     state.activity.insert(id, new_activity_state);
                     state.required_derivations.insert(store_path.clone());
@@ -137,6 +166,9 @@ pub fn process_log(
                         phase: None,
                         progress: None,
                         package_name: Some(package_name),
+                        text: text.clone(),
+                        phases: Vec::new(),
+                        parent,
                     };
                     state.activity.insert(id, new_activity_state);
                     state.required_derivations.insert(store_path.clone());
@@ -150,6 +182,9 @@ pub fn process_log(
                         phase: None,
                         progress: None,
                         package_name: None,
+                        text: text.clone(),
+                        phases: Vec::new(),
+                        parent,
                     };
                     state.activity.insert(id, new_activity_state);
                 } // super::types::Activity::ActCopyPaths => todo!()
@@ -172,6 +207,9 @@ pub fn process_log(
                         phase: v.phase.clone(),
                         progress: v.progress,
                         package_name: v.package_name.clone(),
+                        text: v.text.clone(),
+                        phases: v.phases.clone(),
+                        parent: v.parent,
                     };
                     state.activity.insert(*id, v_updated);
                 }
@@ -185,6 +223,8 @@ pub fn process_log(
                 let id = &act.id;
                 match state.activity.get(id) {
                     Some(v) => {
+                        let mut phases = v.phases.clone();
+                        phases.push((phase.clone(), SystemTime::now()));
                         let v_updated = ActivityState {
                             activity: v.activity.clone(),
                             start: v.start,
@@ -192,6 +232,9 @@ pub fn process_log(
                             phase: Some(phase),
                             progress: v.progress,
                             package_name: v.package_name.clone(),
+                            text: v.text.clone(),
+                            phases,
+                            parent: v.parent,
                         };
                         state.activity.insert(*id, v_updated);
                     }
@@ -211,6 +254,9 @@ pub fn process_log(
                             phase: v.phase.to_owned(),
                             progress: Some(progress),
                             package_name: v.package_name.clone(),
+                            text: v.text.clone(),
+                            phases: v.phases.clone(),
+                            parent: v.parent,
                         };
                         state.activity.insert(*id, v_updated);
                     }
@@ -220,11 +266,13 @@ pub fn process_log(
                 }
             }
             super::types::ActivityResult::BuildLogLine(log) => {
-                let data_about_build = &state.activity.get(&id).unwrap().package_name;
                 let utf8_string = strip_ansi_escapes::strip_str(log);
-                let mut pkg_name = match data_about_build {
-                    Some(p) => p.to_string(),
-                    None => "".to_string(),
+                let mut pkg_name = match state.activity.get(&id) {
+                    Some(v) => v.package_name.to_owned().unwrap_or_default(),
+                    None => {
+                        log::trace!("id not found in the HM BuildLogLine: {}", id);
+                        String::new()
+                    }
                 };
                 if !pkg_name.is_empty() {
                     pkg_name.push('>');
@@ -254,6 +302,7 @@ pub fn process_log(
                     // }
         },
         Some(JSONMessage::Message(act)) => {
+            state.messages.push(act.clone());
             let no_package_name = &"".to_string();
             let pkg_name: &mut String = &mut match state.activity.get(&id) {
                 Some(v) => match &v.package_name {
@@ -264,6 +313,16 @@ pub fn process_log(
             };
             let (lvl, log) = (act.level.to_owned(), act.msg.to_owned());
             let utf8_string = strip_ansi_escapes::strip_str(log);
+            if lvl == Verbosity::Error {
+                state.errors.push(parse_nix_error(&utf8_string));
+            }
+            // the builds nix plans are required before they start
+            state
+                .required_derivations
+                .extend(planned_builds(&utf8_string));
+            if state.first_realise.is_none() {
+                state.eval_messages.push(utf8_string.clone());
+            }
             if pkg_name != no_package_name {
                 pkg_name.push('>');
                 match lvl {
@@ -287,3 +346,19 @@ pub fn process_log(
     }
     state
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nix_logs::parser::parse;
+
+    #[test]
+    fn build_log_line_without_start() {
+        let mut state = CommandState::new();
+        let (msg, id) = parse(
+            r#"@nix {"action":"result","id":9,"type":101,"fields":["make: Nothing to be done"]}"#,
+        );
+        process_log(id, msg, &mut state);
+        assert!(state.activity.is_empty());
+    }
+}
@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone, Copy)]
 pub struct StopAction {
-    pub(crate) id: i64,
+    pub id: i64,
 }
 
 #[derive(Debug, Eq, Serialize, Deserialize, PartialEq, PartialOrd, Clone, Copy)]
@@ -37,30 +37,32 @@ pub enum ActivityType {
 
 #[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone, Copy)]
 pub struct ActivityProgress {
-    pub(crate) done: i64,
-    pub(crate) expected: i64,
-    pub(crate) running: i64,
-    pub(crate) failed: i64,
+    pub done: i64,
+    pub expected: i64,
+    pub running: i64,
+    pub failed: i64,
 }
 
 #[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
 pub struct StartAction {
-    pub(crate) id: i64,
-    pub(crate) level: Verbosity,
-    pub(crate) text: String,
-    pub(crate) activity: Activity,
+    pub id: i64,
+    /// the activity this one runs under, 0 for top level activities
+    pub parent: i64,
+    pub level: Verbosity,
+    pub text: String,
+    pub activity: Activity,
 }
 
 #[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
 pub struct ResultAction {
-    pub(crate) id: i64,
-    pub(crate) result: ActivityResult,
+    pub id: i64,
+    pub result: ActivityResult,
 }
 
 #[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
 pub struct MessageAction {
-    pub(crate) level: Verbosity,
-    pub(crate) msg: String,
+    pub level: Verbosity,
+    pub msg: String,
 }
 
 #[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
@@ -99,4 +101,4 @@ pub enum Activity {
     ActPostBuildHook(String),
     ActBuildWaiting,
     ActFetchTree,
-}
\ No newline at end of file
+}
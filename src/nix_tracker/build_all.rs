@@ -0,0 +1,166 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Error,
+    path::PathBuf,
+    process::ExitStatus,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use yansi::Paint;
+
+use crate::nix_logs::helpers::print_human;
+
+use super::types::JSONCommandState;
+
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub struct TargetRun {
+    pub installable: String,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    /// where the state and the build logs of the target are stored
+    pub run_dir: Option<PathBuf>,
+    pub built: Vec<String>,
+    pub substituted: Vec<String>,
+    pub required_derivations: HashSet<String>,
+    pub errors: Vec<String>,
+}
+
+impl TargetRun {
+    pub fn new(
+        installable: &str,
+        state: &JSONCommandState,
+        status: &ExitStatus,
+        run_dir: Option<PathBuf>,
+    ) -> TargetRun {
+        TargetRun {
+            installable: installable.to_owned(),
+            success: status.success(),
+            exit_code: status.code(),
+            start: state.start,
+            end: state.end,
+            run_dir,
+            built: state
+                .act_build
+                .iter()
+                .map(|b| b.store_path.to_owned())
+                .collect(),
+            substituted: state
+                .act_substitute
+                .iter()
+                .map(|s| s.store_path.to_owned())
+                .collect(),
+            required_derivations: state.required_derivations.clone(),
+            errors: state.errors.iter().map(|e| e.summary()).collect(),
+        }
+    }
+
+    /// A target nix could not even be started for.
+    pub fn not_run(installable: &str, start: SystemTime, err: &Error) -> TargetRun {
+        TargetRun {
+            installable: installable.to_owned(),
+            success: false,
+            exit_code: None,
+            start,
+            end: SystemTime::now(),
+            run_dir: None,
+            built: Vec::new(),
+            substituted: Vec::new(),
+            required_derivations: HashSet::new(),
+            errors: vec![err.to_string()],
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap_or_default()
+    }
+}
+
+/// The targets of one `nixv build-all`, derivations common to several targets counted once.
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub struct BuildAllReport {
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub jobs: usize,
+    pub targets: Vec<TargetRun>,
+    pub built: usize,
+    pub substituted: usize,
+    /// derivations needed by more than one target
+    pub shared: usize,
+}
+
+impl BuildAllReport {
+    pub fn new(start: SystemTime, jobs: usize, targets: Vec<TargetRun>) -> BuildAllReport {
+        let built: HashSet<&String> = targets.iter().flat_map(|t| t.built.iter()).collect();
+        let substituted: HashSet<&String> =
+            targets.iter().flat_map(|t| t.substituted.iter()).collect();
+        let mut needed_by: HashMap<&String, usize> = HashMap::new();
+        for drv in targets.iter().flat_map(|t| t.required_derivations.iter()) {
+            *needed_by.entry(drv).or_default() += 1;
+        }
+        BuildAllReport {
+            start,
+            end: SystemTime::now(),
+            jobs,
+            built: built.len(),
+            substituted: substituted.len(),
+            shared: needed_by.values().filter(|n| **n > 1).count(),
+            targets,
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.targets.iter().all(|t| t.success)
+    }
+
+    pub fn print(&self) {
+        let width = self
+            .targets
+            .iter()
+            .map(|t| t.installable.len())
+            .max()
+            .unwrap_or_default();
+        for t in self.targets.iter() {
+            let status = match t.success {
+                true => Paint::green("ok    ").to_string(),
+                false => Paint::red("failed").to_string(),
+            };
+            print_human(format!(
+                "{} {:width$} {:>10.1?} {} built, {} substituted",
+                status,
+                t.installable,
+                t.duration(),
+                t.built.len(),
+                t.substituted.len(),
+                width = width
+            ));
+            for err in t.errors.iter() {
+                print_human(format!("       {}", err));
+            }
+        }
+        let failed = self.targets.iter().filter(|t| !t.success).count();
+        print_human(format!(
+            "{}/{} targets succeeded in {:?} with {} jobs",
+            self.targets.len() - failed,
+            self.targets.len(),
+            self.end.duration_since(self.start).unwrap_or_default(),
+            self.jobs
+        ));
+        print_human(format!(
+            "{} derivations built, {} paths substituted, {} derivations shared by several targets",
+            self.built, self.substituted, self.shared
+        ));
+    }
+
+    pub fn dump_to_file(&self, file_name: &str) {
+        let written = serde_json::to_string_pretty(self)
+            .map_err(Error::from)
+            .and_then(|json_dump| fs::write(file_name, json_dump));
+        if let Err(err) = written {
+            log::warn!("unable to write the report to {}: {}", file_name, err);
+        }
+    }
+}
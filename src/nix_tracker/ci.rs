@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Write as _,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::nix_logs::{
+    errors::NixError,
+    helpers::print_human,
+    types::{Activity, ActivityResult, JSONMessage},
+};
+
+use super::{
+    format::{format_duration, format_size},
+    hash_mismatch::locate_hash,
+    types::{CommandState, JSONCommandState},
+};
+
+const SLOWEST_BUILDS: usize = 10;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum CiProvider {
+    Github,
+    Gitlab,
+}
+
+impl CiProvider {
+    pub fn detect() -> Option<CiProvider> {
+        let is_set = |var: &str| env::var(var).map(|v| v == "true").unwrap_or(false);
+        if is_set("GITHUB_ACTIONS") {
+            Some(CiProvider::Github)
+        } else if is_set("GITLAB_CI") {
+            Some(CiProvider::Gitlab)
+        } else {
+            None
+        }
+    }
+}
+
+/// Holds the build log of every running derivation and prints it as one
+/// collapsible group when the build stops, so parallel builds don't interleave.
+pub struct CiLog {
+    provider: CiProvider,
+    logs: HashMap<i64, Vec<String>>,
+}
+
+fn section_name(id: i64) -> String {
+    format!("nix_build_{}", id)
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl CiLog {
+    pub fn new(provider: CiProvider) -> CiLog {
+        CiLog {
+            provider,
+            logs: HashMap::new(),
+        }
+    }
+
+    /// Takes over build log lines, returns whether the message was one.
+    pub fn record(&mut self, id: i64, msg: &Option<JSONMessage>, state: &CommandState) -> bool {
+        match msg {
+            Some(JSONMessage::Result(res)) => match &res.result {
+                ActivityResult::BuildLogLine(line) | ActivityResult::PostBuildLogLine(line) => {
+                    self.logs
+                        .entry(id)
+                        .or_default()
+                        .push(strip_ansi_escapes::strip_str(line));
+                    true
+                }
+                _ => false,
+            },
+            Some(JSONMessage::Stop(stop)) => {
+                self.print_group(stop.id, state);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn print_group(&mut self, id: i64, state: &CommandState) {
+        let lines = match self.logs.remove(&id) {
+            Some(lines) => lines,
+            None => return,
+        };
+        let title = match state.activity.get(&id) {
+            Some(act) => {
+                let name = match &act.activity {
+                    Activity::ActBuild(package_name, _, _, _, _) => package_name.to_owned(),
+                    _ => act.package_name.clone().unwrap_or(act.text.to_owned()),
+                };
+                format!(
+                    "{} ({:.1}s)",
+                    name,
+                    act.start.elapsed().unwrap_or_default().as_secs_f64()
+                )
+            }
+            None => format!("activity {}", id),
+        };
+        match self.provider {
+            CiProvider::Github => {
+                print_human(format!("::group::{}", title));
+                lines.iter().for_each(print_human);
+                print_human("::endgroup::");
+            }
+            CiProvider::Gitlab => gitlab_section(&section_name(id), &title, &lines, true),
+        }
+    }
+
+    /// Prints the logs of builds that never stopped, usually because nix was interrupted.
+    pub fn finish(mut self, state: &CommandState) {
+        let mut ids: Vec<i64> = self.logs.keys().copied().collect();
+        ids.sort();
+        for id in ids {
+            self.print_group(id, state);
+        }
+    }
+}
+
+fn gitlab_section(name: &str, title: &str, lines: &[String], collapsed: bool) {
+    print_human(format!(
+        "\x1b[0Ksection_start:{}:{}[collapsed={}]\r\x1b[0K{}",
+        unix_time(),
+        name,
+        collapsed,
+        title
+    ));
+    lines.iter().for_each(print_human);
+    print_human(format!(
+        "\x1b[0Ksection_end:{}:{}\r\x1b[0K",
+        unix_time(),
+        name
+    ));
+}
+
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+fn relative(file: &Path, root: &Path) -> String {
+    file.strip_prefix(root)
+        .unwrap_or(file)
+        .display()
+        .to_string()
+}
+
+fn error_command(file: Option<(String, i64, Option<i64>)>, title: &str, message: &str) -> String {
+    let location = match file {
+        Some((file, line, Some(column))) => format!(
+            "file={},line={},col={},",
+            escape_property(&file),
+            line,
+            column
+        ),
+        Some((file, line, None)) => format!("file={},line={},", escape_property(&file), line),
+        None => String::new(),
+    };
+    format!(
+        "::error {}title={}::{}",
+        location,
+        escape_property(title),
+        escape_data(message)
+    )
+}
+
+/// GitHub error annotations, pointing at the nix file when the error has a position. GitLab has
+/// no annotations, the errors get a section of their own at the end of the job log.
+pub fn print_annotations(provider: CiProvider, errors: &[NixError]) {
+    if errors.is_empty() {
+        return;
+    }
+    if provider == CiProvider::Gitlab {
+        let lines: Vec<String> = errors
+            .iter()
+            .flat_map(|err| {
+                err.summary()
+                    .lines()
+                    .map(|l| l.to_owned())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let title = format!("nix errors ({})", errors.len());
+        gitlab_section("nix_errors", &title, &lines, false);
+        return;
+    }
+    let root = env::var("GITHUB_WORKSPACE")
+        .map(PathBuf::from)
+        .or(env::current_dir())
+        .unwrap_or(PathBuf::from("."));
+    for err in errors {
+        match err {
+            NixError::Evaluation {
+                message,
+                position: Some(p),
+                ..
+            } => print_human(error_command(
+                Some((relative(Path::new(&p.file), &root), p.line, Some(p.column))),
+                "nix evaluation error",
+                message,
+            )),
+            NixError::HashMismatch {
+                package_name,
+                specified,
+                got,
+                ..
+            } => {
+                let locations = locate_hash(&root, specified, got);
+                match locations.first() {
+                    Some(location) => print_human(error_command(
+                        Some((relative(&location.file, &root), location.line as i64, None)),
+                        &format!("hash mismatch in {}", package_name),
+                        &format!("specified: {}\ngot: {}", specified, got),
+                    )),
+                    None => print_human(error_command(None, "nix error", &err.summary())),
+                }
+            }
+            _ => print_human(error_command(None, "nix error", &err.summary())),
+        }
+    }
+}
+
+/// Markdown summary of the run: totals, the cache hit rate, what became of the store paths and
+/// the slowest builds.
+pub fn step_summary(state: &JSONCommandState) -> String {
+    let mut md = String::from("## nixv\n\n");
+    let built = state.act_build.len();
+    let substituted = state.act_substitute.len();
+    let total = state.end.duration_since(state.start).unwrap_or_default();
+    let _ = writeln!(md, "| | |\n|---|---|");
+    let _ = writeln!(md, "| total time | {} |", format_duration(total));
+    if let Some(eval) = &state.eval {
+        let _ = writeln!(md, "| evaluation | {} |", format_duration(eval.duration));
+    }
+    let _ = writeln!(md, "| built | {} |", built);
+    let _ = writeln!(md, "| substituted | {} |", substituted);
+    if built + substituted > 0 {
+        let _ = writeln!(
+            md,
+            "| cache hit rate | {:.0}% |",
+            100.0 * substituted as f64 / (built + substituted) as f64
+        );
+    }
+    let _ = writeln!(md, "| errors | {} |", state.errors.len());
+    if let Some(closure) = &state.closure {
+        let _ = writeln!(
+            md,
+            "| closure size | {}{} |",
+            format_size(closure.closure_size),
+            closure
+                .delta()
+                .map(|delta| format!(" ({})", delta))
+                .unwrap_or_default()
+        );
+    }
+    if let Some(dispositions) = &state.dispositions {
+        if !dispositions.counts.is_empty() {
+            let _ = writeln!(
+                md,
+                "\n### Store paths\n\n| disposition | paths |\n|---|---|"
+            );
+            for count in dispositions.counts.iter() {
+                let _ = writeln!(md, "| {} | {} |", count.disposition, count.count);
+            }
+        }
+    }
+
+    let mut builds: Vec<_> = state
+        .act_build
+        .iter()
+        .map(|b| {
+            (
+                b.end.duration_since(b.start).unwrap_or_default(),
+                &b.package_name,
+            )
+        })
+        .collect();
+    builds.sort_by_key(|(duration, _)| std::cmp::Reverse(*duration));
+    if !builds.is_empty() {
+        let _ = writeln!(
+            md,
+            "\n### Slowest builds\n\n| derivation | time |\n|---|---|"
+        );
+        for (duration, package_name) in builds.iter().take(SLOWEST_BUILDS) {
+            let _ = writeln!(
+                md,
+                "| `{}` | {} |",
+                package_name,
+                format_duration(*duration)
+            );
+        }
+    }
+    if !state.errors.is_empty() {
+        let _ = writeln!(md, "\n### Errors\n");
+        for err in state.errors.iter() {
+            let _ = writeln!(md, "- {}", err.summary().replace('\n', " "));
+        }
+    }
+    md
+}
+
+/// Appends the step summary to `$GITHUB_STEP_SUMMARY` when it is set, prints it in a collapsed
+/// section on GitLab.
+pub fn write_step_summary(provider: CiProvider, state: &JSONCommandState) {
+    if provider == CiProvider::Gitlab {
+        let lines: Vec<String> = step_summary(state).lines().map(|l| l.to_owned()).collect();
+        gitlab_section("nixv_summary", "nixv summary", &lines, true);
+        return;
+    }
+    let file = match env::var("GITHUB_STEP_SUMMARY") {
+        Ok(file) if !file.is_empty() => file,
+        _ => return,
+    };
+    let written = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&file)
+        .and_then(|mut f| f.write_all(step_summary(state).as_bytes()));
+    if let Err(err) = written {
+        log::warn!("unable to write the step summary to {}: {}", file, err);
+    }
+}
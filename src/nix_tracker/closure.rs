@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    io::Error,
+    process::{self as PC, Stdio},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    nix_history::runs::{list_runs, read_run_state, run_id},
+    nix_logs::helpers::print_human,
+};
+
+use super::{format::format_size, types::CommandState};
+
+/// How many of the largest store paths of the closure are kept.
+const LARGEST: usize = 10;
+
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub struct PathSize {
+    pub path: String,
+    pub nar_size: i64,
+    /// the path and everything it depends on
+    pub closure_size: i64,
+}
+
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ClosureReport {
+    pub outputs: Vec<PathSize>,
+    /// every path of the closure of the outputs, counted once
+    pub closure_size: i64,
+    pub paths: usize,
+    pub largest: Vec<PathSize>,
+    /// the previous run of the same command and the size of its closure
+    pub previous_run: Option<String>,
+    pub previous_closure_size: Option<i64>,
+}
+
+fn path_size(path: &str, info: &Value) -> PathSize {
+    let int = |key: &str| info.get(key).and_then(|v| v.as_i64()).unwrap_or_default();
+    PathSize {
+        path: path.to_owned(),
+        nar_size: int("narSize"),
+        closure_size: int("closureSize"),
+    }
+}
+
+/// Reads `nix path-info --json`, a list of objects with a `path` in older versions of nix and
+/// an object keyed by path in newer ones.
+pub(crate) fn path_infos(json: &Value) -> Vec<(&str, &Value)> {
+    match json {
+        Value::Array(infos) => infos
+            .iter()
+            .filter_map(|info| Some((info.get("path")?.as_str()?, info)))
+            .collect(),
+        Value::Object(infos) => infos
+            .iter()
+            .filter(|(_, info)| !info.is_null())
+            .map(|(path, info)| (path.as_str(), info))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn query_path_info(outputs: &[String]) -> Result<Vec<PathSize>, Error> {
+    let output = PC::Command::new("nix")
+        .arg("--extra-experimental-features")
+        .arg("nix-command")
+        .arg("path-info")
+        .arg("-S")
+        .arg("-r")
+        .arg("--json")
+        .args(outputs)
+        .stderr(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::other(format!(
+            "nix path-info failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let json: Value = serde_json::from_slice(&output.stdout)?;
+    Ok(path_infos(&json)
+        .into_iter()
+        .map(|(path, info)| path_size(path, info))
+        .collect())
+}
+
+/// The closure size of the latest stored run of the same command.
+fn previous_closure(command: &[String]) -> Option<(String, i64)> {
+    list_runs().iter().find_map(|run_dir| {
+        let state = read_run_state(run_dir)?;
+        match state.command == command {
+            true => Some((run_id(run_dir), state.closure?.closure_size)),
+            false => None,
+        }
+    })
+}
+
+impl ClosureReport {
+    pub fn query(outputs: &[String], command: &[String]) -> Result<ClosureReport, Error> {
+        let infos = query_path_info(outputs)?;
+        let by_path: HashMap<&str, &PathSize> =
+            infos.iter().map(|i| (i.path.as_str(), i)).collect();
+        let mut largest: Vec<PathSize> = infos.clone();
+        largest.sort_by_key(|i| std::cmp::Reverse(i.nar_size));
+        largest.truncate(LARGEST);
+        let previous = previous_closure(command);
+        Ok(ClosureReport {
+            outputs: outputs
+                .iter()
+                .filter_map(|o| by_path.get(o.as_str()).map(|i| (*i).clone()))
+                .collect(),
+            closure_size: infos.iter().map(|i| i.nar_size).sum(),
+            paths: infos.len(),
+            largest,
+            previous_run: previous.as_ref().map(|(run, _)| run.to_owned()),
+            previous_closure_size: previous.map(|(_, size)| size),
+        })
+    }
+
+    /// Queries the closure of the outputs of a successful run.
+    pub fn collect(state: &mut CommandState) {
+        if state.outputs.is_empty() {
+            return;
+        }
+        match ClosureReport::query(&state.outputs, &state.command) {
+            Ok(report) => state.closure = Some(report),
+            Err(err) => log::warn!("unable to get the size of the outputs: {}", err),
+        }
+    }
+
+    /// The growth since the previous run, like `+1.2 MiB`.
+    pub fn delta(&self) -> Option<String> {
+        let delta = self.closure_size - self.previous_closure_size?;
+        let sign = if delta < 0 { "-" } else { "+" };
+        Some(format!("{}{}", sign, format_size(delta.abs())))
+    }
+
+    pub fn print(&self) {
+        for output in self.outputs.iter() {
+            print_human(format!(
+                "{}: {} ({} with its closure)",
+                output.path,
+                format_size(output.nar_size),
+                format_size(output.closure_size)
+            ));
+        }
+        let delta = match (&self.previous_run, self.delta()) {
+            (Some(run), Some(delta)) => format!(", {} since {}", delta, run),
+            _ => String::new(),
+        };
+        print_human(format!(
+            "closure size: {} in {} paths{}",
+            format_size(self.closure_size),
+            self.paths,
+            delta
+        ));
+        for path in self.largest.iter().take(5) {
+            log::info!("  {:>10} {}", format_size(path.nar_size), path.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO: &str = "/nix/store/8bj9zs3ynqxwkdkfsb9ciw6ww1ny4ndk-hello-2.12.1";
+    const GLIBC: &str = "/nix/store/ddwyrxif62r8n6xclvskjyy6szdhvj60-glibc-2.39-5";
+
+    fn sizes(json: &str) -> Vec<PathSize> {
+        let json: Value = serde_json::from_str(json).unwrap();
+        let mut sizes: Vec<PathSize> = path_infos(&json)
+            .into_iter()
+            .map(|(path, info)| path_size(path, info))
+            .collect();
+        sizes.sort_by(|a, b| a.path.cmp(&b.path));
+        sizes
+    }
+
+    fn report(closure_size: i64, previous_closure_size: Option<i64>) -> ClosureReport {
+        ClosureReport {
+            outputs: Vec::new(),
+            closure_size,
+            paths: 0,
+            largest: Vec::new(),
+            previous_run: None,
+            previous_closure_size,
+        }
+    }
+
+    #[test]
+    fn path_infos_reads_both_shapes_of_path_info() {
+        let expected = vec![
+            PathSize {
+                path: HELLO.to_owned(),
+                nar_size: 270_000,
+                closure_size: 30_470_000,
+            },
+            PathSize {
+                path: GLIBC.to_owned(),
+                nar_size: 30_000_000,
+                closure_size: 30_200_000,
+            },
+        ];
+        let array = format!(
+            r#"[{{"path":"{HELLO}","narSize":270000,"closureSize":30470000}},
+                {{"path":"{GLIBC}","narSize":30000000,"closureSize":30200000}}]"#
+        );
+        assert_eq!(sizes(&array), expected);
+        // newer versions key the infos by path, with null for paths that aren't valid
+        let object = format!(
+            r#"{{"{HELLO}":{{"narSize":270000,"closureSize":30470000}},
+                "{GLIBC}":{{"narSize":30000000,"closureSize":30200000}},
+                "/nix/store/00000000000000000000000000000000-missing":null}}"#
+        );
+        assert_eq!(sizes(&object), expected);
+        assert_eq!(sizes("null"), Vec::new());
+    }
+
+    #[test]
+    fn delta_is_signed() {
+        assert_eq!(report(3 << 20, Some(1 << 20)).delta().unwrap(), "+2.0 MiB");
+        assert_eq!(
+            report(1 << 20, Some(3 << 19)).delta().unwrap(),
+            "-512.0 KiB"
+        );
+        assert_eq!(report(1024, Some(1024)).delta().unwrap(), "+0 B");
+        assert_eq!(report(1024, None).delta(), None);
+    }
+}
@@ -0,0 +1,345 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::nix_logs::{
+    errors::NixError, helpers::print_human, parser::get_package_from_drv, types::Activity,
+};
+
+use super::{drv_diff::path_name, types::CommandState};
+
+/// How many of the failed and skipped derivations are named in the summary.
+const LISTED: usize = 5;
+
+/// What became of a store path the run required, from the least to the most telling.
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, PartialOrd, Ord, Clone)]
+pub enum Disposition {
+    /// looked up in the caches, which nix only does for paths that are not valid, and then
+    /// neither fetched nor built
+    Queried,
+    /// only copied, so valid before the run
+    AlreadyValid,
+    /// in the plan nix printed but never started
+    Planned,
+    Substituted {
+        cache: String,
+    },
+    BuiltLocally,
+    BuiltRemotely {
+        host: String,
+    },
+    /// not built because a derivation it depends on failed
+    DependencyFailed,
+    Failed,
+}
+
+impl fmt::Display for Disposition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Disposition::Queried => write!(f, "queried, not fetched"),
+            Disposition::AlreadyValid => write!(f, "already valid"),
+            Disposition::Planned => write!(f, "planned, not built"),
+            Disposition::Substituted { cache } => write!(f, "substituted from {}", cache),
+            Disposition::BuiltLocally => write!(f, "built locally"),
+            Disposition::BuiltRemotely { host } => write!(f, "built on {}", host),
+            Disposition::DependencyFailed => write!(f, "skipped, a dependency failed"),
+            Disposition::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+impl Disposition {
+    /// Whether the summary names the paths, for the ones the run did not get to.
+    fn is_listed(&self) -> bool {
+        matches!(
+            self,
+            Disposition::Planned | Disposition::DependencyFailed | Disposition::Failed
+        )
+    }
+}
+
+/// The derivation of `path` among `drvs`, by name: `hello-2.12.1` and `hello-2.12.1-man` are
+/// outputs of `hello-2.12.1.drv`. Paths whose name several derivations share stay apart.
+fn deriver<'a>(path: &'a str, drvs: &BTreeMap<&str, Vec<&'a str>>) -> Option<&'a str> {
+    if path.ends_with(".drv") {
+        return Some(path);
+    }
+    let name = path_name(path);
+    let output = |drv_name: &str| match name.strip_prefix(drv_name) {
+        Some("") => true,
+        Some(rest) => rest.strip_prefix('-').is_some_and(|output| {
+            output.starts_with(|c: char| c.is_ascii_alphabetic()) && !output.contains('-')
+        }),
+        None => false,
+    };
+    drvs.iter()
+        .filter(|(drv_name, _)| output(drv_name))
+        .max_by_key(|(drv_name, _)| drv_name.len())
+        .and_then(|(_, paths)| match paths.as_slice() {
+            [drv] => Some(*drv),
+            _ => None,
+        })
+}
+
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub struct PathDisposition {
+    pub package_name: String,
+    pub store_path: String,
+    pub disposition: Disposition,
+}
+
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub struct DispositionCount {
+    pub disposition: Disposition,
+    pub count: usize,
+}
+
+/// The final disposition of every store path of `required_derivations`, and of the
+/// derivations skipped because of a failed dependency.
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub struct DispositionReport {
+    pub counts: Vec<DispositionCount>,
+    pub paths: Vec<PathDisposition>,
+}
+
+impl DispositionReport {
+    /// A path can go through several activities, a build that then fails for instance, the most
+    /// telling disposition is kept. Outputs count as their derivation when the run has it.
+    pub fn from_state(state: &CommandState) -> DispositionReport {
+        let mut seen: Vec<(&str, String, Disposition)> = Vec::new();
+        for act in state.activity.values() {
+            match &act.activity {
+                Activity::ActBuild(package_name, drv_path, host, ..) => {
+                    let disposition = match host.as_str() {
+                        "" | "local" => Disposition::BuiltLocally,
+                        host => Disposition::BuiltRemotely {
+                            host: host.to_owned(),
+                        },
+                    };
+                    seen.push((drv_path, package_name.to_owned(), disposition));
+                }
+                Activity::ActSubstitute(package_name, store_path, from) => seen.push((
+                    store_path,
+                    package_name.to_owned(),
+                    Disposition::Substituted {
+                        cache: from.to_owned(),
+                    },
+                )),
+                Activity::ActQueryPathInfo(package_name, store_path, _) => {
+                    seen.push((store_path, package_name.to_owned(), Disposition::Queried))
+                }
+                Activity::ActCopyPath(package_name, store_path, ..) => seen.push((
+                    store_path,
+                    package_name.to_owned(),
+                    Disposition::AlreadyValid,
+                )),
+                _ => {}
+            }
+        }
+        // failed builds are told by the errors nix reports, which come last
+        for err in state.errors.iter() {
+            let disposition = match err {
+                NixError::DependencyFailed { .. } => Disposition::DependencyFailed,
+                NixError::BuilderFailed { .. }
+                | NixError::HashMismatch { .. }
+                | NixError::NotDeterministic { .. } => Disposition::Failed,
+                NixError::Evaluation { .. } | NixError::Other(_) => continue,
+            };
+            if let Some(drv_path) = err.drv_path() {
+                seen.push((
+                    drv_path,
+                    get_package_from_drv(drv_path.to_owned()),
+                    disposition,
+                ));
+            }
+        }
+        // the plan nix printed and the post-build hooks only name derivations
+        for path in state.required_derivations.iter() {
+            if path.ends_with(".drv") {
+                seen.push((
+                    path,
+                    get_package_from_drv(path.to_owned()),
+                    Disposition::Planned,
+                ));
+            }
+        }
+        let mut drvs: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for (path, ..) in seen.iter().filter(|(path, ..)| path.ends_with(".drv")) {
+            let paths = drvs.entry(path_name(path)).or_default();
+            if !paths.contains(path) {
+                paths.push(path);
+            }
+        }
+        let mut paths: BTreeMap<&str, (String, Disposition)> = BTreeMap::new();
+        for (path, package_name, disposition) in seen {
+            let (path, package_name) = match deriver(path, &drvs) {
+                Some(drv) if drv != path => (drv, get_package_from_drv(drv.to_owned())),
+                _ => (path, package_name),
+            };
+            match paths.get_mut(path) {
+                Some(entry) if entry.1 >= disposition => {}
+                Some(entry) => entry.1 = disposition,
+                None => {
+                    paths.insert(path, (package_name, disposition));
+                }
+            }
+        }
+        DispositionReport::new(
+            paths
+                .into_iter()
+                .map(
+                    |(store_path, (package_name, disposition))| PathDisposition {
+                        package_name,
+                        store_path: store_path.to_owned(),
+                        disposition,
+                    },
+                )
+                .collect(),
+        )
+    }
+
+    fn new(mut paths: Vec<PathDisposition>) -> DispositionReport {
+        paths.sort_by(|a, b| {
+            (&a.disposition, &a.package_name).cmp(&(&b.disposition, &b.package_name))
+        });
+        let mut counts: Vec<DispositionCount> = Vec::new();
+        for path in paths.iter() {
+            match counts.last_mut() {
+                Some(last) if last.disposition == path.disposition => last.count += 1,
+                _ => counts.push(DispositionCount {
+                    disposition: path.disposition.clone(),
+                    count: 1,
+                }),
+            }
+        }
+        DispositionReport { counts, paths }
+    }
+
+    pub fn print(&self) {
+        if self.paths.is_empty() {
+            return;
+        }
+        print_human(format!("{} store paths required:", self.paths.len()));
+        for count in self.counts.iter() {
+            log::info!("  {:>5} {}", count.count, count.disposition);
+            if count.disposition.is_listed() {
+                let names: Vec<&str> = self
+                    .paths
+                    .iter()
+                    .filter(|p| p.disposition == count.disposition)
+                    .map(|p| p.package_name.as_str())
+                    .collect();
+                let more = match names.len() > LISTED {
+                    true => format!(" and {} more", names.len() - LISTED),
+                    false => String::new(),
+                };
+                log::info!(
+                    "        {}{}",
+                    names[..names.len().min(LISTED)].join(", "),
+                    more
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::nix_tracker::types::ActivityState;
+
+    const HELLO_DRV: &str = "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv";
+    const HELLO: &str = "/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-hello-2.12.1";
+    const HELLO_MAN: &str = "/nix/store/cccccccccccccccccccccccccccccccc-hello-2.12.1-man";
+    const ZLIB: &str = "/nix/store/dddddddddddddddddddddddddddddddd-zlib-1.3";
+    const CURL: &str = "/nix/store/eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee-curl-8.5.0";
+    const APP_DRV: &str = "/nix/store/ffffffffffffffffffffffffffffffff-app-1.0.drv";
+
+    fn add(state: &mut CommandState, id: i64, activity: Activity) {
+        state.activity.insert(
+            id,
+            ActivityState {
+                activity,
+                start: SystemTime::now(),
+                end: None,
+                phase: None,
+                progress: None,
+                package_name: None,
+                text: String::new(),
+                phases: Vec::new(),
+                parent: None,
+            },
+        );
+    }
+
+    fn disposition(report: &DispositionReport, path: &str) -> Option<Disposition> {
+        report
+            .paths
+            .iter()
+            .find(|p| p.store_path == path)
+            .map(|p| p.disposition.clone())
+    }
+
+    #[test]
+    fn outputs_count_as_their_derivation() {
+        let mut state = CommandState::new();
+        let cache = "https://cache.nixos.org".to_owned();
+        for (id, path) in [(1, HELLO), (2, HELLO_MAN), (3, ZLIB), (4, CURL)] {
+            let name = get_package_from_drv(path.to_owned());
+            add(
+                &mut state,
+                id,
+                Activity::ActQueryPathInfo(name, path.to_owned(), cache.clone()),
+            );
+        }
+        add(
+            &mut state,
+            5,
+            Activity::ActBuild(
+                "hello-2.12.1".to_owned(),
+                HELLO_DRV.to_owned(),
+                String::new(),
+                1,
+                1,
+            ),
+        );
+        add(
+            &mut state,
+            6,
+            Activity::ActSubstitute("zlib-1.3".to_owned(), ZLIB.to_owned(), cache.clone()),
+        );
+        state
+            .required_derivations
+            .extend([HELLO, HELLO_MAN, ZLIB, CURL, HELLO_DRV, APP_DRV].map(|p| p.to_owned()));
+        let report = DispositionReport::from_state(&state);
+        assert_eq!(report.paths.len(), 4);
+        assert_eq!(
+            disposition(&report, HELLO_DRV),
+            Some(Disposition::BuiltLocally)
+        );
+        assert_eq!(disposition(&report, HELLO), None);
+        assert_eq!(
+            disposition(&report, ZLIB),
+            Some(Disposition::Substituted { cache })
+        );
+        assert_eq!(disposition(&report, CURL), Some(Disposition::Queried));
+        assert_eq!(disposition(&report, APP_DRV), Some(Disposition::Planned));
+    }
+
+    #[test]
+    fn deriver_needs_an_output_name() {
+        let drvs = BTreeMap::from([("hello-2.12.1", vec![HELLO_DRV])]);
+        assert_eq!(deriver(HELLO, &drvs), Some(HELLO_DRV));
+        assert_eq!(deriver(HELLO_MAN, &drvs), Some(HELLO_DRV));
+        assert_eq!(
+            deriver(
+                "/nix/store/gggggggggggggggggggggggggggggggg-hello-2.12.1-2",
+                &drvs
+            ),
+            None
+        );
+        assert_eq!(deriver(ZLIB, &drvs), None);
+    }
+}
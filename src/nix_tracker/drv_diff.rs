@@ -0,0 +1,543 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::Error,
+    process::{self as PC, Stdio},
+};
+
+use serde_json::Value;
+use yansi::Paint;
+
+use crate::nix_logs::helpers::print_human;
+
+use super::{closure::path_infos, eta::pname};
+
+/// Values with more lines than that on both sides are not diffed line by line.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// A derivation as read from `nix derivation show`.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct Derivation {
+    pub path: String,
+    pub name: String,
+    pub system: String,
+    pub builder: String,
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    /// output name to output path, content-addressed outputs have no path
+    pub outputs: BTreeMap<String, String>,
+    pub input_drvs: BTreeSet<String>,
+    pub input_srcs: BTreeSet<String>,
+}
+
+/// `/nix/store/<hash>-hello-2.12.1.drv` is `hello-2.12.1`.
+pub fn path_name(path: &str) -> &str {
+    let base = path.rsplit('/').next().unwrap_or(path);
+    let name = base.split_once('-').map(|(_, name)| name).unwrap_or(base);
+    name.strip_suffix(".drv").unwrap_or(name)
+}
+
+/// Newer versions of nix leave out the store directory.
+fn store_path(path: &str) -> String {
+    match path.starts_with('/') {
+        true => path.to_owned(),
+        false => format!("/nix/store/{}", path),
+    }
+}
+
+fn nix_json(args: &[&str], paths: &[String]) -> Result<Value, Error> {
+    let output = PC::Command::new("nix")
+        .arg("--extra-experimental-features")
+        .arg("nix-command")
+        .args(args)
+        .args(paths)
+        .stderr(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::other(format!(
+            "nix {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+impl Derivation {
+    /// Reads both the format of older versions of nix, with `inputDrvs` and `inputSrcs`, and
+    /// the one with `inputs.drvs` and `inputs.srcs`.
+    fn from_json(path: &str, json: &Value) -> Derivation {
+        let str = |v: &Value| v.as_str().unwrap_or_default().to_owned();
+        let input_drvs = json
+            .get("inputDrvs")
+            .or_else(|| json.get("inputs").and_then(|i| i.get("drvs")));
+        let input_srcs = json
+            .get("inputSrcs")
+            .or_else(|| json.get("inputs").and_then(|i| i.get("srcs")));
+        Derivation {
+            path: store_path(path),
+            name: str(&json["name"]),
+            system: str(&json["system"]),
+            builder: str(&json["builder"]),
+            args: match json.get("args") {
+                Some(Value::Array(args)) => args.iter().map(str).collect(),
+                _ => Vec::new(),
+            },
+            env: match json.get("env") {
+                Some(Value::Object(env)) => {
+                    env.iter().map(|(k, v)| (k.to_owned(), str(v))).collect()
+                }
+                _ => BTreeMap::new(),
+            },
+            outputs: match json.get("outputs") {
+                Some(Value::Object(outputs)) => outputs
+                    .iter()
+                    .filter_map(|(name, o)| {
+                        Some((name.to_owned(), store_path(o.get("path")?.as_str()?)))
+                    })
+                    .collect(),
+                _ => BTreeMap::new(),
+            },
+            input_drvs: match input_drvs {
+                Some(Value::Object(drvs)) => drvs.keys().map(|d| store_path(d)).collect(),
+                _ => BTreeSet::new(),
+            },
+            input_srcs: match input_srcs {
+                Some(Value::Array(srcs)) => srcs.iter().map(|s| store_path(&str(s))).collect(),
+                _ => BTreeSet::new(),
+            },
+        }
+    }
+}
+
+/// The derivations at `paths`, and all they depend on when `recursive` is set.
+///
+/// The `.drv` files have to still be in the store.
+pub fn show_derivations(
+    paths: &[String],
+    recursive: bool,
+) -> Result<HashMap<String, Derivation>, Error> {
+    let args: &[&str] = match recursive {
+        true => &["derivation", "show", "-r"],
+        false => &["derivation", "show"],
+    };
+    let json = nix_json(args, paths)?;
+    let drvs = match &json {
+        Value::Object(drvs) => drvs
+            .iter()
+            .map(|(path, drv)| (store_path(path), Derivation::from_json(path, drv)))
+            .collect(),
+        _ => HashMap::new(),
+    };
+    Ok(drvs)
+}
+
+/// The derivations that produced store paths, for the paths still in the store.
+pub fn derivers(paths: &[String]) -> Result<HashMap<String, String>, Error> {
+    let json = nix_json(&["path-info", "--json"], paths)?;
+    Ok(path_infos(&json)
+        .into_iter()
+        .filter_map(|(path, info)| {
+            let deriver = info.get("deriver")?.as_str()?;
+            Some((path.to_owned(), store_path(deriver)))
+        })
+        .collect())
+}
+
+/// A store path in one derivation against its counterpart in the other, matched by name.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum PathChange {
+    Changed { old: String, new: String },
+    Added(String),
+    Removed(String),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct EnvChange {
+    pub name: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// How two derivations differ.
+///
+/// Store paths that only changed because an input did are not differences: env vars, builder
+/// and args are compared once the paths of the old inputs are replaced by the new ones.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct DrvDiff {
+    pub old: String,
+    pub new: String,
+    pub system: Option<(String, String)>,
+    pub builder: Option<(String, String)>,
+    pub args: Option<(Vec<String>, Vec<String>)>,
+    pub env: Vec<EnvChange>,
+    pub inputs: Vec<PathChange>,
+    pub srcs: Vec<PathChange>,
+    /// store paths of the old derivation and their counterpart in the new one
+    pub renames: Vec<(String, String)>,
+}
+
+/// Pairs the paths only one side has by name, and then by package name so that a new version
+/// of an input is a change rather than a removal and an addition.
+fn match_paths(old: &BTreeSet<String>, new: &BTreeSet<String>) -> Vec<PathChange> {
+    let mut removed: Vec<&String> = old.difference(new).collect();
+    let mut added: Vec<&String> = new.difference(old).collect();
+    let mut changes = Vec::new();
+    let keys: [fn(&str) -> &str; 2] = [path_name, |p| pname(path_name(p))];
+    for key in keys {
+        removed.retain(
+            |old| match added.iter().position(|new| key(new) == key(old)) {
+                Some(i) => {
+                    changes.push(PathChange::Changed {
+                        old: old.to_string(),
+                        new: added.remove(i).to_owned(),
+                    });
+                    false
+                }
+                None => true,
+            },
+        );
+    }
+    changes.extend(
+        removed
+            .into_iter()
+            .map(|p| PathChange::Removed(p.to_owned())),
+    );
+    changes.extend(added.into_iter().map(|p| PathChange::Added(p.to_owned())));
+    changes
+}
+
+/// The paths of the outputs of `old` with the matching outputs of `new`.
+fn output_renames(old: &Derivation, new: &Derivation) -> Vec<(String, String)> {
+    old.outputs
+        .iter()
+        .filter_map(|(name, path)| Some((path.to_owned(), new.outputs.get(name)?.to_owned())))
+        .collect()
+}
+
+fn rewrite(value: &str, renames: &[(String, String)]) -> String {
+    renames.iter().fold(value.to_owned(), |value, (old, new)| {
+        value.replace(old, new)
+    })
+}
+
+impl DrvDiff {
+    /// `drvs` holds the derivations of the inputs, from [`show_derivations`] with `recursive`,
+    /// to tell the paths of their outputs.
+    pub fn compare(
+        old: &Derivation,
+        new: &Derivation,
+        drvs: &HashMap<String, Derivation>,
+    ) -> DrvDiff {
+        let inputs = match_paths(&old.input_drvs, &new.input_drvs);
+        let srcs = match_paths(&old.input_srcs, &new.input_srcs);
+        let mut renames = vec![(old.path.to_owned(), new.path.to_owned())];
+        renames.extend(output_renames(old, new));
+        for change in inputs.iter().chain(srcs.iter()) {
+            if let PathChange::Changed { old, new } = change {
+                if let (Some(old), Some(new)) = (drvs.get(old), drvs.get(new)) {
+                    renames.extend(output_renames(old, new));
+                }
+                renames.push((old.to_owned(), new.to_owned()));
+            }
+        }
+        // hashes of different lengths never happen, but the longest paths go first anyway
+        renames.sort_by_key(|(old, _)| std::cmp::Reverse(old.len()));
+        let changed = |a: &str, b: &str| match rewrite(a, &renames) == b {
+            true => None,
+            false => Some((a.to_owned(), b.to_owned())),
+        };
+        let keys: BTreeSet<&String> = old.env.keys().chain(new.env.keys()).collect();
+        let env = keys
+            .into_iter()
+            .filter_map(|name| {
+                let (a, b) = (old.env.get(name), new.env.get(name));
+                let same = match (a, b) {
+                    (Some(a), Some(b)) => rewrite(a, &renames) == *b,
+                    _ => false,
+                };
+                match same {
+                    true => None,
+                    false => Some(EnvChange {
+                        name: name.to_owned(),
+                        old: a.cloned(),
+                        new: b.cloned(),
+                    }),
+                }
+            })
+            .collect();
+        let old_args: Vec<String> = old.args.iter().map(|a| rewrite(a, &renames)).collect();
+        DrvDiff {
+            old: old.path.to_owned(),
+            new: new.path.to_owned(),
+            system: match old.system == new.system {
+                true => None,
+                false => Some((old.system.to_owned(), new.system.to_owned())),
+            },
+            builder: changed(&old.builder, &new.builder),
+            args: match old_args == new.args {
+                true => None,
+                false => Some((old.args.to_owned(), new.args.to_owned())),
+            },
+            env,
+            inputs,
+            srcs,
+            renames,
+        }
+    }
+
+    /// The input derivations that are now another derivation of the same name.
+    pub fn changed_inputs(&self) -> Vec<(&str, &str)> {
+        self.inputs
+            .iter()
+            .filter_map(|change| match change {
+                PathChange::Changed { old, new } => Some((old.as_str(), new.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// What changed in the derivation itself rather than in the derivations it depends on, in
+    /// a few words.
+    pub fn own_changes(&self) -> Vec<String> {
+        let mut changes = Vec::new();
+        if let Some((old, new)) = &self.system {
+            changes.push(format!("system {} -> {}", old, new));
+        }
+        if self.builder.is_some() {
+            changes.push("builder".to_owned());
+        }
+        if self.args.is_some() {
+            changes.push("builder args".to_owned());
+        }
+        for change in self.srcs.iter() {
+            changes.push(match change {
+                PathChange::Changed { new, .. } => format!("source {}", path_name(new)),
+                PathChange::Added(path) => format!("source +{}", path_name(path)),
+                PathChange::Removed(path) => format!("source -{}", path_name(path)),
+            });
+        }
+        for change in self.inputs.iter() {
+            changes.push(match change {
+                PathChange::Changed { .. } => continue,
+                PathChange::Added(path) => format!("input +{}", path_name(path)),
+                PathChange::Removed(path) => format!("input -{}", path_name(path)),
+            });
+        }
+        if !self.env.is_empty() {
+            let names: Vec<&str> = self.env.iter().map(|e| e.name.as_str()).collect();
+            changes.push(format!("env {}", names.join(", ")));
+        }
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty() && self.own_changes().is_empty()
+    }
+
+    /// Prints the diff, with the old paths in values replaced by the new ones so that only the
+    /// lines that really differ show up.
+    pub fn print(&self) {
+        print_human(Paint::red(&format!("--- {}", self.old)));
+        print_human(Paint::green(&format!("+++ {}", self.new)));
+        if let Some((old, new)) = &self.system {
+            print_human(format!("system: {} -> {}", old, new));
+        }
+        if let Some((old, new)) = &self.builder {
+            print_human("builder:");
+            print_lines(&self.rewrite(old), new);
+        }
+        if let Some((old, new)) = &self.args {
+            let old: Vec<String> = old.iter().map(|a| self.rewrite(a)).collect();
+            print_human("builder args:");
+            print_lines(&old.join("\n"), &new.join("\n"));
+        }
+        for (title, changes) in [("inputs:", &self.inputs), ("sources:", &self.srcs)] {
+            if changes.is_empty() {
+                continue;
+            }
+            print_human(title);
+            for change in changes {
+                print_human(match change {
+                    PathChange::Changed { old, new } if path_name(old) == path_name(new) => {
+                        format!("  ~ {}", path_name(new))
+                    }
+                    PathChange::Changed { old, new } => {
+                        format!("  ~ {} -> {}", path_name(old), path_name(new))
+                    }
+                    PathChange::Added(path) => Paint::green(&format!("  + {}", path)).to_string(),
+                    PathChange::Removed(path) => Paint::red(&format!("  - {}", path)).to_string(),
+                });
+            }
+        }
+        if !self.env.is_empty() {
+            print_human("env:");
+        }
+        for change in self.env.iter() {
+            print_human(format!("  {}:", change.name));
+            match (&change.old, &change.new) {
+                (Some(old), Some(new)) => print_lines(&self.rewrite(old), new),
+                (Some(old), None) => print_human(Paint::red(&format!("    - {}", quoted(old)))),
+                (None, Some(new)) => print_human(Paint::green(&format!("    + {}", quoted(new)))),
+                (None, None) => {}
+            }
+        }
+    }
+
+    fn rewrite(&self, value: &str) -> String {
+        rewrite(value, &self.renames)
+    }
+}
+
+fn quoted(value: &str) -> String {
+    match value.is_empty() {
+        true => "\"\"".to_owned(),
+        false => value.to_owned(),
+    }
+}
+
+/// Prints the lines only one of the values has.
+fn print_lines(old: &str, new: &str) {
+    for (tag, line) in line_diff(old, new) {
+        match tag {
+            '-' => print_human(Paint::red(&format!("    - {}", quoted(line)))),
+            '+' => print_human(Paint::green(&format!("    + {}", quoted(line)))),
+            _ => {}
+        }
+    }
+}
+
+/// The lines of both values tagged `-` when only `old` has them, `+` when only `new` has them,
+/// or ` `, from their longest common subsequence.
+pub fn line_diff<'a>(old: &'a str, new: &'a str) -> Vec<(char, &'a str)> {
+    let old: Vec<&str> = old.split('\n').collect();
+    let new: Vec<&str> = new.split('\n').collect();
+    let (n, m) = (old.len(), new.len());
+    if n * m > MAX_DIFF_CELLS {
+        let removed = old.into_iter().map(|l| ('-', l));
+        return removed.chain(new.into_iter().map(|l| ('+', l))).collect();
+    }
+    // common[i][j] is the length of the common subsequence of old[i..] and new[j..]
+    let mut common = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i][j] = match old[i] == new[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if i < n && (j == m || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO: &str = "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv";
+    const HELLO_NEW: &str = "/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-hello-2.12.1.drv";
+    const HELLO_BUMP: &str = "/nix/store/cccccccccccccccccccccccccccccccc-hello-2.12.2.drv";
+    const ZLIB: &str = "/nix/store/dddddddddddddddddddddddddddddddd-zlib-1.3.drv";
+    const CURL: &str = "/nix/store/eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee-curl-8.5.0.drv";
+
+    fn set(paths: &[&str]) -> BTreeSet<String> {
+        paths.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn line_diff_keeps_common_lines() {
+        assert_eq!(
+            line_diff("a\nb\nc", "a\nx\nc"),
+            vec![(' ', "a"), ('-', "b"), ('+', "x"), (' ', "c")]
+        );
+        assert_eq!(line_diff("a\nb", "a\nb"), vec![(' ', "a"), (' ', "b")]);
+        assert_eq!(
+            line_diff("a\nc", "a\nb\nc"),
+            vec![(' ', "a"), ('+', "b"), (' ', "c")]
+        );
+        assert_eq!(line_diff("", "a"), vec![('-', ""), ('+', "a")]);
+    }
+
+    #[test]
+    fn match_paths_pairs_by_name_then_package() {
+        let changes = match_paths(&set(&[HELLO, ZLIB]), &set(&[HELLO_NEW, ZLIB]));
+        assert_eq!(
+            changes,
+            vec![PathChange::Changed {
+                old: HELLO.to_owned(),
+                new: HELLO_NEW.to_owned(),
+            }]
+        );
+        let changes = match_paths(&set(&[HELLO, ZLIB]), &set(&[HELLO_BUMP, CURL]));
+        assert_eq!(
+            changes,
+            vec![
+                PathChange::Changed {
+                    old: HELLO.to_owned(),
+                    new: HELLO_BUMP.to_owned(),
+                },
+                PathChange::Removed(ZLIB.to_owned()),
+                PathChange::Added(CURL.to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rewrite_replaces_every_renamed_path() {
+        let renames = vec![
+            (HELLO.to_owned(), HELLO_NEW.to_owned()),
+            (ZLIB.to_owned(), CURL.to_owned()),
+        ];
+        assert_eq!(
+            rewrite(&format!("{HELLO}:{ZLIB}:{HELLO}"), &renames),
+            format!("{HELLO_NEW}:{CURL}:{HELLO_NEW}")
+        );
+        assert_eq!(rewrite("unrelated", &renames), "unrelated");
+    }
+
+    #[test]
+    fn compare_ignores_paths_of_changed_inputs() {
+        let old = Derivation {
+            path: "/nix/store/ffffffffffffffffffffffffffffffff-app-1.0.drv".to_owned(),
+            input_drvs: set(&[HELLO]),
+            env: BTreeMap::from([
+                ("PATH".to_owned(), format!("{HELLO}/bin")),
+                ("flags".to_owned(), "-O2".to_owned()),
+            ]),
+            ..Derivation::default()
+        };
+        let new = Derivation {
+            path: "/nix/store/gggggggggggggggggggggggggggggggg-app-1.0.drv".to_owned(),
+            input_drvs: set(&[HELLO_NEW]),
+            env: BTreeMap::from([
+                ("PATH".to_owned(), format!("{HELLO_NEW}/bin")),
+                ("flags".to_owned(), "-O3".to_owned()),
+            ]),
+            ..Derivation::default()
+        };
+        let diff = DrvDiff::compare(&old, &new, &HashMap::new());
+        assert_eq!(
+            diff.env,
+            vec![EnvChange {
+                name: "flags".to_owned(),
+                old: Some("-O2".to_owned()),
+                new: Some("-O3".to_owned()),
+            }]
+        );
+        assert_eq!(diff.changed_inputs(), vec![(HELLO, HELLO_NEW)]);
+        assert!(diff.system.is_none() && diff.builder.is_none() && diff.args.is_none());
+    }
+}
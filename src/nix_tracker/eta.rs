@@ -0,0 +1,502 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant, SystemTime},
+};
+
+use yansi::Paint;
+
+use crate::{
+    nix_history::runs::{list_runs, read_run_state},
+    nix_logs::{
+        parser::get_package_from_drv,
+        types::{Activity, ActivityResult, ActivityType, JSONMessage},
+    },
+};
+
+use super::types::{CommandState, JSONCommandState};
+
+/// Older runs than that are not read, the recent ones are the most telling anyway.
+const MAX_RUNS: usize = 50;
+/// Share of the name tokens two packages need in common to be taken as alike.
+const MIN_SIMILARITY: f64 = 0.5;
+/// How often the ETA of the run and of the running builds is printed while builds run.
+const REPORT_INTERVAL: Duration = Duration::from_secs(30);
+/// How often a report is tried while no build with an estimate runs.
+const ATTEMPT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where an estimate comes from, from the most to the least reliable.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum EstimateSource {
+    SameDerivation,
+    SameVersion,
+    /// another version of the package
+    SamePackage,
+    /// a package with a similar name
+    SimilarName(String),
+    /// the typical build of the previous runs
+    Typical,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Estimate {
+    pub duration: Duration,
+    pub source: EstimateSource,
+}
+
+/// `hello-2.12.1` is a build of `hello`, the version starts at the first `-<digit>`.
+pub fn pname(name: &str) -> &str {
+    name.match_indices('-')
+        .find(|(i, _)| {
+            name[i + 1..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_digit())
+        })
+        .map(|(i, _)| &name[..i])
+        .unwrap_or(name)
+}
+
+fn tokens(name: &str) -> HashSet<&str> {
+    pname(name)
+        .split(['-', '_', '.'])
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+fn similarity(a: &HashSet<&str>, b: &HashSet<&str>) -> f64 {
+    let union = a.union(b).count();
+    match union {
+        0 => 0.0,
+        union => a.intersection(b).count() as f64 / union as f64,
+    }
+}
+
+fn median(durations: &[Duration]) -> Option<Duration> {
+    let mut sorted = durations.to_vec();
+    sorted.sort();
+    sorted.get(sorted.len() / 2).copied()
+}
+
+/// How long derivations took to build in the previous runs, failed builds left out.
+#[derive(Debug, Default, Clone)]
+pub struct BuildHistory {
+    by_drv: HashMap<String, Duration>,
+    by_name: HashMap<String, Vec<Duration>>,
+    by_pname: HashMap<String, Vec<Duration>>,
+    all: Vec<Duration>,
+}
+
+impl BuildHistory {
+    /// The builds of the stored runs, newest first.
+    pub fn load() -> BuildHistory {
+        let mut history = BuildHistory::default();
+        for run_dir in list_runs().iter().take(MAX_RUNS) {
+            if let Some(state) = read_run_state(run_dir) {
+                history.add(&state);
+            }
+        }
+        history
+    }
+
+    /// Adds the builds of a run, runs are expected newest first.
+    pub fn add(&mut self, state: &JSONCommandState) {
+        for build in state.act_build.iter() {
+            let failed = state
+                .errors
+                .iter()
+                .any(|e| e.drv_path() == Some(build.store_path.as_str()));
+            if failed {
+                continue;
+            }
+            let duration = build.end.duration_since(build.start).unwrap_or_default();
+            self.by_drv
+                .entry(build.store_path.to_owned())
+                .or_insert(duration);
+            self.by_name
+                .entry(build.package_name.to_owned())
+                .or_default()
+                .push(duration);
+            self.by_pname
+                .entry(pname(&build.package_name).to_owned())
+                .or_default()
+                .push(duration);
+            self.all.push(duration);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.all.is_empty()
+    }
+
+    pub fn estimate(&self, drv_path: &str, package_name: &str) -> Option<Estimate> {
+        let estimate = |duration, source| Some(Estimate { duration, source });
+        if let Some(duration) = self.by_drv.get(drv_path) {
+            return estimate(*duration, EstimateSource::SameDerivation);
+        }
+        if let Some(duration) = self.by_name.get(package_name).and_then(|d| median(d)) {
+            return estimate(duration, EstimateSource::SameVersion);
+        }
+        if let Some(duration) = self
+            .by_pname
+            .get(pname(package_name))
+            .and_then(|d| median(d))
+        {
+            return estimate(duration, EstimateSource::SamePackage);
+        }
+        let wanted = tokens(package_name);
+        let similar = self
+            .by_pname
+            .iter()
+            .map(|(name, durations)| (similarity(&wanted, &tokens(name)), name, durations))
+            .filter(|(score, ..)| *score >= MIN_SIMILARITY)
+            .max_by(|a, b| a.0.total_cmp(&b.0));
+        if let Some((_, name, durations)) = similar {
+            if let Some(duration) = median(durations) {
+                return estimate(duration, EstimateSource::SimilarName(name.to_owned()));
+            }
+        }
+        estimate(median(&self.all)?, EstimateSource::Typical)
+    }
+}
+
+fn format_eta(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// The builds of a plan like `these 3 derivations will be built:` followed by their paths.
+pub fn planned_builds(msg: &str) -> Vec<String> {
+    if !msg.contains("will be built:") {
+        return Vec::new();
+    }
+    msg.lines()
+        .map(|l| l.trim())
+        .filter(|l| l.starts_with("/nix/store/") && l.ends_with(".drv"))
+        .map(|l| l.to_owned())
+        .collect()
+}
+
+/// Estimates, from the history of the builds, when each build and the whole run will finish.
+///
+/// What is left is known from the derivations of `required_derivations` that were not built
+/// yet, nix adds the plan it prints to them, and from the number of builds nix expects.
+pub struct Eta {
+    history: BuildHistory,
+    expected_builds: HashMap<i64, i64>,
+    last_report: Option<Instant>,
+    last_attempt: Option<Instant>,
+}
+
+impl Eta {
+    pub fn new(history: BuildHistory) -> Eta {
+        Eta {
+            history,
+            expected_builds: HashMap::new(),
+            last_report: None,
+            last_attempt: None,
+        }
+    }
+
+    /// Reads a message against the state before it is processed.
+    pub fn record(&mut self, id: i64, msg: &Option<JSONMessage>, state: &CommandState) {
+        match msg {
+            Some(JSONMessage::Result(result)) => {
+                if let ActivityResult::SetExpected(ActivityType::ActBuildType, expected) =
+                    result.result
+                {
+                    self.expected_builds.insert(result.id, expected);
+                }
+            }
+            Some(JSONMessage::Start(start)) => {
+                if let Activity::ActBuild(package_name, drv_path, ..) = &start.activity {
+                    if let Some(estimate) = self.history.estimate(drv_path, package_name) {
+                        log::info!(
+                            "{} expected to take {}",
+                            Paint::green(&format!("{}>", package_name)),
+                            self.describe(&estimate)
+                        );
+                    }
+                }
+            }
+            Some(JSONMessage::Stop(_)) => {
+                let stopping = state.activity.get(&id);
+                if let Some(Activity::ActBuild(..)) = stopping.map(|a| &a.activity) {
+                    if let Some((left @ 1.., eta)) = self.remaining(state, Some(id)) {
+                        log::info!("{} builds left, about {} to go", left, format_eta(eta));
+                    }
+                    self.last_report = Some(Instant::now());
+                    return;
+                }
+            }
+            Some(JSONMessage::Message(_)) | None => {}
+        }
+        let due = |last: Option<Instant>, interval| match last {
+            Some(last) => last.elapsed() >= interval,
+            None => true,
+        };
+        if due(self.last_report, REPORT_INTERVAL) && due(self.last_attempt, ATTEMPT_INTERVAL) {
+            self.last_attempt = Some(Instant::now());
+            self.report(state);
+        }
+    }
+
+    /// Prints the time left for the run and for each running build, once builds run.
+    fn report(&mut self, state: &CommandState) {
+        let mut running: Vec<(&str, Duration)> = state
+            .activity
+            .iter()
+            .filter(|(_, act)| act.end.is_none())
+            .filter_map(|(id, act)| match &act.activity {
+                Activity::ActBuild(package_name, ..) => {
+                    Some((package_name.as_str(), self.build_eta(*id, state)?))
+                }
+                _ => None,
+            })
+            .collect();
+        if running.is_empty() {
+            return;
+        }
+        let (left, eta) = match self.remaining(state, None) {
+            Some(remaining) => remaining,
+            None => return,
+        };
+        self.last_report = Some(Instant::now());
+        running.sort_by_key(|(_, eta)| std::cmp::Reverse(*eta));
+        let builds: Vec<String> = running
+            .iter()
+            .map(|(package_name, eta)| match eta.is_zero() {
+                true => format!("{} should be done by now", package_name),
+                false => format!("{}: {}", package_name, format_eta(*eta)),
+            })
+            .collect();
+        log::info!(
+            "{} builds left, about {} to go ({})",
+            left,
+            format_eta(eta),
+            builds.join(", ")
+        );
+    }
+
+    fn describe(&self, estimate: &Estimate) -> String {
+        let duration = format_eta(estimate.duration);
+        match &estimate.source {
+            EstimateSource::SameDerivation => duration,
+            EstimateSource::SameVersion => format!("{} (other builds of this version)", duration),
+            EstimateSource::SamePackage => format!("{} (other versions)", duration),
+            EstimateSource::SimilarName(name) => format!("{} (like {})", duration, name),
+            EstimateSource::Typical => format!("{} (a typical build)", duration),
+        }
+    }
+
+    /// The remaining time of a running build, `None` without history.
+    pub fn build_eta(&self, id: i64, state: &CommandState) -> Option<Duration> {
+        let act = state.activity.get(&id)?;
+        match &act.activity {
+            Activity::ActBuild(package_name, drv_path, ..) => {
+                let estimate = self.history.estimate(drv_path, package_name)?;
+                let elapsed = SystemTime::now()
+                    .duration_since(act.start)
+                    .unwrap_or_default();
+                Some(estimate.duration.saturating_sub(elapsed))
+            }
+            _ => None,
+        }
+    }
+
+    /// The builds left and the time until the run ends, `stopping` is a build ending now.
+    ///
+    /// The work left is spread over as many builds as are running, but the run lasts at least
+    /// as long as its longest running build.
+    pub fn remaining(
+        &self,
+        state: &CommandState,
+        stopping: Option<i64>,
+    ) -> Option<(usize, Duration)> {
+        if self.history.is_empty() {
+            return None;
+        }
+        let builds: Vec<(&i64, &String)> = state
+            .activity
+            .iter()
+            .filter_map(|(id, act)| match &act.activity {
+                Activity::ActBuild(_, drv_path, ..) => Some((id, drv_path)),
+                _ => None,
+            })
+            .collect();
+        let started: HashSet<&String> = builds.iter().map(|(_, drv)| *drv).collect();
+        let running: Vec<i64> = builds
+            .iter()
+            .map(|(id, _)| **id)
+            .filter(|id| state.activity[id].end.is_none() && Some(*id) != stopping)
+            .collect();
+        let running_left: Vec<Duration> = running
+            .iter()
+            .filter_map(|id| self.build_eta(*id, state))
+            .collect();
+        let mut work: Duration = running_left.iter().sum();
+        let mut left = running.len();
+        let not_started: Vec<&String> = state
+            .required_derivations
+            .iter()
+            .filter(|path| path.ends_with(".drv") && !started.contains(path))
+            .collect();
+        for drv_path in not_started.iter() {
+            let package_name = get_package_from_drv(drv_path.to_string());
+            if let Some(estimate) = self.history.estimate(drv_path, &package_name) {
+                work += estimate.duration;
+            }
+        }
+        left += not_started.len();
+        // builds nix expects beyond the ones it named, without -v or in nested nix calls
+        let expected: i64 = self.expected_builds.values().sum();
+        let unnamed = (expected - (started.len() + not_started.len()) as i64).max(0) as usize;
+        if let Some(typical) = median(&self.history.all) {
+            work += typical * unnamed as u32;
+        }
+        left += unnamed;
+        let jobs = (running.len() + stopping.is_some() as usize).max(1) as u32;
+        let longest = running_left.iter().max().copied().unwrap_or_default();
+        Some((left, longest.max(work / jobs)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nix_tracker::types::ActivityState;
+
+    const HELLO_DRV: &str = "/nix/store/8bj9zs3ynqxwkdkfsb9ciw6ww1ny4ndk-hello-2.12.1.drv";
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn history() -> BuildHistory {
+        let mut history = BuildHistory::default();
+        history.by_drv.insert(HELLO_DRV.to_owned(), secs(10));
+        history.by_name.insert(
+            "hello-2.12.1".to_owned(),
+            vec![secs(20), secs(22), secs(24)],
+        );
+        history.by_pname.insert("hello".to_owned(), vec![secs(30)]);
+        history
+            .by_pname
+            .insert("python3-requests".to_owned(), vec![secs(40)]);
+        history.all = vec![secs(10), secs(50), secs(60)];
+        history
+    }
+
+    fn build(drv_path: &str, end: Option<SystemTime>) -> ActivityState {
+        let package_name = get_package_from_drv(drv_path.to_owned());
+        ActivityState {
+            activity: Activity::ActBuild(
+                package_name.to_owned(),
+                drv_path.to_owned(),
+                String::new(),
+                1,
+                1,
+            ),
+            start: SystemTime::now(),
+            end,
+            phase: None,
+            progress: None,
+            package_name: Some(package_name),
+            text: String::new(),
+            phases: Vec::new(),
+            parent: None,
+        }
+    }
+
+    #[test]
+    fn pname_stops_at_the_version() {
+        assert_eq!(pname("hello-2.12.1"), "hello");
+        assert_eq!(pname("python3.11-requests-2.31.0"), "python3.11-requests");
+        assert_eq!(pname("gcc-wrapper"), "gcc-wrapper");
+        assert_eq!(pname("hello-"), "hello-");
+    }
+
+    #[test]
+    fn similarity_is_the_share_of_common_tokens() {
+        let a = tokens("python3-requests-2.31.0");
+        assert_eq!(similarity(&a, &tokens("python3-urllib3-2.0.7")), 1.0 / 3.0);
+        assert_eq!(similarity(&a, &tokens("python3_requests")), 1.0);
+        assert_eq!(similarity(&a, &tokens("hello-2.12.1")), 0.0);
+        assert_eq!(similarity(&HashSet::new(), &HashSet::new()), 0.0);
+    }
+
+    #[test]
+    fn estimate_falls_back_from_the_derivation_to_a_typical_build() {
+        let history = history();
+        let source = |drv_path: &str, package_name: &str| {
+            let estimate = history.estimate(drv_path, package_name).unwrap();
+            (estimate.duration, estimate.source)
+        };
+        let other_drv = "/nix/store/0000000000000000000000000000000-hello-2.12.1.drv";
+        assert_eq!(
+            source(HELLO_DRV, "hello-2.12.1"),
+            (secs(10), EstimateSource::SameDerivation)
+        );
+        assert_eq!(
+            source(other_drv, "hello-2.12.1"),
+            (secs(22), EstimateSource::SameVersion)
+        );
+        assert_eq!(
+            source(other_drv, "hello-2.13"),
+            (secs(30), EstimateSource::SamePackage)
+        );
+        assert_eq!(
+            source(other_drv, "python3-requests-toolbelt-1.0.0"),
+            (
+                secs(40),
+                EstimateSource::SimilarName("python3-requests".to_owned())
+            )
+        );
+        assert_eq!(
+            source(other_drv, "zlib-1.3"),
+            (secs(50), EstimateSource::Typical)
+        );
+        assert_eq!(BuildHistory::default().estimate(HELLO_DRV, "hello"), None);
+    }
+
+    #[test]
+    fn remaining_counts_running_planned_and_expected_builds() {
+        let mut eta = Eta::new(history());
+        let mut state = CommandState::new();
+        assert_eq!(eta.remaining(&state, None), Some((0, Duration::ZERO)));
+        state.activity.insert(1, build(HELLO_DRV, None));
+        state.activity.insert(
+            2,
+            build("/nix/store/1111-zlib-1.3.drv", Some(SystemTime::now())),
+        );
+        state
+            .required_derivations
+            .insert("/nix/store/2222-python3-requests-2.31.0.drv".to_owned());
+        let (left, remaining) = eta.remaining(&state, None).unwrap();
+        assert_eq!(left, 2);
+        // hello has 10s left and requests 40s, one build at a time
+        assert!(
+            remaining > secs(48) && remaining <= secs(50),
+            "{remaining:?}"
+        );
+        // nix expects 2 more builds than it named, typical ones
+        eta.expected_builds.insert(7, 5);
+        let (left, remaining) = eta.remaining(&state, None).unwrap();
+        assert_eq!(left, 4);
+        assert!(
+            remaining > secs(148) && remaining <= secs(150),
+            "{remaining:?}"
+        );
+        // hello stopping now leaves the other builds
+        let (left, remaining) = eta.remaining(&state, Some(1)).unwrap();
+        assert_eq!(left, 3);
+        assert_eq!(remaining, secs(140));
+        assert_eq!(
+            Eta::new(BuildHistory::default()).remaining(&state, None),
+            None
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{self as PC},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::nix_logs::{helpers::print_human, types::Activity};
+
+use super::types::CommandState;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct FetchTree {
+    pub description: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct EvalStats {
+    pub cpu_time: Option<f64>,
+    pub nr_thunks: Option<i64>,
+    pub nr_function_calls: Option<i64>,
+    pub nr_lookups: Option<i64>,
+    pub nr_values: Option<i64>,
+    pub nr_envs: Option<i64>,
+    pub values_bytes: Option<i64>,
+    pub envs_bytes: Option<i64>,
+    pub gc_heap_size: Option<i64>,
+    pub gc_total_bytes: Option<i64>,
+    pub raw: Value,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct EvalReport {
+    pub start: SystemTime,
+    pub first_realise: Option<SystemTime>,
+    pub duration: Duration,
+    pub fetch_tree_duration: Duration,
+    pub fetch_trees: Vec<FetchTree>,
+    pub messages: Vec<String>,
+    pub stats: Option<EvalStats>,
+    pub profile_file: Option<String>,
+}
+
+/// Activities that only start once evaluation has produced something to realise.
+pub fn is_realise_activity(activity: &Activity) -> bool {
+    matches!(
+        activity,
+        Activity::ActRealise
+            | Activity::ActBuilds
+            | Activity::ActBuild(..)
+            | Activity::ActCopyPaths
+            | Activity::ActSubstitute(..)
+    )
+}
+
+/// Commands started so far, `nixv build-all` runs several at once.
+static COMMANDS: AtomicUsize = AtomicUsize::new(0);
+
+/// One file per command.
+fn stats_file() -> PathBuf {
+    let command = COMMANDS.fetch_add(1, Ordering::Relaxed);
+    env::temp_dir().join(format!("nixv-eval-stats-{}-{}.json", PC::id(), command))
+}
+
+/// Makes nix write its evaluator statistics (`EVAL_STATS=true`) and an evaluation
+/// profile (`EVAL_PROFILE=<file>`, needs the `eval-profiler` setting in nix). Returns the
+/// file the statistics are written to.
+pub fn enable_eval_stats(cmd: &mut PC::Command) -> Option<PathBuf> {
+    let show_stats = match env::var("EVAL_STATS") {
+        Ok(value) => value.parse().unwrap_or_default(),
+        Err(_) => false,
+    };
+    let stats_file = match show_stats {
+        true => Some(stats_file()),
+        false => None,
+    };
+    if let Some(stats_file) = &stats_file {
+        cmd.env("NIX_SHOW_STATS", "1")
+            .env("NIX_SHOW_STATS_PATH", stats_file);
+    }
+    if let Ok(profile_file) = env::var("EVAL_PROFILE") {
+        // passed as settings so that they never end up after `--command`
+        let nix_config = env::var("NIX_CONFIG").unwrap_or_default();
+        cmd.env(
+            "NIX_CONFIG",
+            format!(
+                "{}\neval-profiler = flamegraph\neval-profile-file = {}",
+                nix_config, profile_file
+            ),
+        );
+    }
+    stats_file
+}
+
+fn read_eval_stats(file: &Path) -> Option<EvalStats> {
+    let content = fs::read_to_string(file).ok()?;
+    let _ = fs::remove_file(file);
+    let raw: Value = serde_json::from_str(&content).ok()?;
+    let int = |path: &[&str]| {
+        path.iter()
+            .try_fold(&raw, |v, key| v.get(key))
+            .and_then(|v| v.as_i64())
+    };
+    Some(EvalStats {
+        cpu_time: raw.get("cpuTime").and_then(|v| v.as_f64()),
+        nr_thunks: int(&["nrThunks"]),
+        nr_function_calls: int(&["nrFunctionCalls"]),
+        nr_lookups: int(&["nrLookups"]),
+        nr_values: int(&["values", "number"]),
+        nr_envs: int(&["envs", "number"]),
+        values_bytes: int(&["values", "bytes"]),
+        envs_bytes: int(&["envs", "bytes"]),
+        gc_heap_size: int(&["gc", "heapSize"]),
+        gc_total_bytes: int(&["gc", "totalBytes"]),
+        raw,
+    })
+}
+
+impl EvalReport {
+    pub fn from_state(state: &CommandState) -> EvalReport {
+        let end = state
+            .first_realise
+            .or(state.end)
+            .unwrap_or(SystemTime::now());
+        let mut fetch_trees: Vec<FetchTree> = state
+            .activity
+            .values()
+            .filter(|act| act.activity == Activity::ActFetchTree)
+            .map(|act| FetchTree {
+                description: act.text.to_owned(),
+                start: act.start,
+                end: act.end.unwrap_or(end),
+            })
+            .collect();
+        fetch_trees.sort_by_key(|f| f.start);
+        let fetch_tree_duration = fetch_trees
+            .iter()
+            .map(|f| f.end.duration_since(f.start).unwrap_or_default())
+            .sum();
+        EvalReport {
+            start: state.start,
+            first_realise: state.first_realise,
+            duration: end.duration_since(state.start).unwrap_or_default(),
+            fetch_tree_duration,
+            fetch_trees,
+            messages: state.eval_messages.clone(),
+            stats: state.eval_stats.clone(),
+            profile_file: env::var("EVAL_PROFILE").ok(),
+        }
+    }
+
+    pub fn collect_stats(state: &mut CommandState, stats_file: Option<&Path>) {
+        state.eval_stats = stats_file.and_then(read_eval_stats);
+    }
+
+    pub fn print(&self) {
+        print_human(format!(
+            "time spent in evaluation: {:?} (fetching inputs: {:?})",
+            self.duration, self.fetch_tree_duration
+        ));
+        let mut slowest: Vec<&FetchTree> = self.fetch_trees.iter().collect();
+        slowest.sort_by_key(|f| std::cmp::Reverse(f.end.duration_since(f.start).ok()));
+        for f in slowest.iter().take(5) {
+            log::info!(
+                "  {:?} {}",
+                f.end.duration_since(f.start).unwrap_or_default(),
+                f.description
+            );
+        }
+        if let Some(stats) = &self.stats {
+            log::info!(
+                "  eval cpu time: {:.3}s, thunks: {}, function calls: {}, values: {}, envs: {}, allocated: {} bytes",
+                stats.cpu_time.unwrap_or_default(),
+                stats.nr_thunks.unwrap_or_default(),
+                stats.nr_function_calls.unwrap_or_default(),
+                stats.nr_values.unwrap_or_default(),
+                stats.nr_envs.unwrap_or_default(),
+                stats.gc_total_bytes.unwrap_or_default()
+            );
+        }
+        if let Some(profile_file) = &self.profile_file {
+            log::info!("  evaluation profile written to {}", profile_file);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+    process::ExitStatus,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::nix_logs::{
+    errors::{parse_nix_error, NixError},
+    types::{Activity, ActivityResult, JSONMessage, Verbosity},
+};
+
+use super::types::CommandState;
+
+/// Bumped whenever a field is removed or changes meaning, additions keep the version.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// nixv's interpretation of a line of the nix log.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    ActivityStarted {
+        id: i64,
+        parent: Option<i64>,
+        activity_type: String,
+        package_name: Option<String>,
+        store_path: Option<String>,
+        text: String,
+    },
+    ActivityStopped {
+        id: i64,
+        activity_type: String,
+        package_name: Option<String>,
+        duration_ms: u64,
+    },
+    PhaseChanged {
+        id: i64,
+        package_name: Option<String>,
+        phase: String,
+    },
+    Progress {
+        id: i64,
+        package_name: Option<String>,
+        done: i64,
+        expected: i64,
+        running: i64,
+        failed: i64,
+    },
+    LogLine {
+        id: i64,
+        package_name: Option<String>,
+        line: String,
+    },
+    Message {
+        level: String,
+        package_name: Option<String>,
+        text: String,
+    },
+    Error {
+        summary: String,
+        error: NixError,
+    },
+    RunFinished {
+        success: bool,
+        exit_code: Option<i32>,
+        duration_ms: u64,
+        run_dir: Option<PathBuf>,
+    },
+}
+
+/// A line of the NDJSON output.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct EventRecord {
+    pub version: u32,
+    /// milliseconds since the unix epoch
+    pub time: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+pub fn activity_type_name(activity: &Activity) -> &'static str {
+    match activity {
+        Activity::ActUnknown => "unknown",
+        Activity::ActCopyPath(..) => "copy_path",
+        Activity::ActFileTransfer(_) => "file_transfer",
+        Activity::ActRealise => "realise",
+        Activity::ActCopyPaths => "copy_paths",
+        Activity::ActBuilds => "builds",
+        Activity::ActBuild(..) => "build",
+        Activity::ActOptimiseStore => "optimise_store",
+        Activity::ActVerifyPaths => "verify_paths",
+        Activity::ActSubstitute(..) => "substitute",
+        Activity::ActQueryPathInfo(..) => "query_path_info",
+        Activity::ActPostBuildHook(_) => "post_build_hook",
+        Activity::ActBuildWaiting => "build_waiting",
+        Activity::ActFetchTree => "fetch_tree",
+    }
+}
+
+fn package_and_path(activity: &Activity) -> (Option<String>, Option<String>) {
+    match activity {
+        Activity::ActCopyPath(package_name, store_path, _, _)
+        | Activity::ActBuild(package_name, store_path, _, _, _)
+        | Activity::ActSubstitute(package_name, store_path, _)
+        | Activity::ActQueryPathInfo(package_name, store_path, _) => {
+            (Some(package_name.to_owned()), Some(store_path.to_owned()))
+        }
+        Activity::ActPostBuildHook(store_path) => (None, Some(store_path.to_owned())),
+        _ => (None, None),
+    }
+}
+
+fn level_name(level: &Verbosity) -> String {
+    format!("{:?}", level).to_lowercase()
+}
+
+fn millis(duration: std::time::Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+/// The events of a message, read against the state before the message is processed.
+pub fn events_for(id: i64, msg: &Option<JSONMessage>, state: &CommandState) -> Vec<Event> {
+    let package_name = || state.activity.get(&id).and_then(|a| a.package_name.clone());
+    match msg {
+        Some(JSONMessage::Start(start)) => {
+            let (package_name, store_path) = package_and_path(&start.activity);
+            vec![Event::ActivityStarted {
+                id: start.id,
+                parent: Some(start.parent).filter(|p| *p != 0),
+                activity_type: activity_type_name(&start.activity).to_owned(),
+                package_name,
+                store_path,
+                text: start.text.to_owned(),
+            }]
+        }
+        Some(JSONMessage::Stop(stop)) => match state.activity.get(&stop.id) {
+            Some(act) => vec![Event::ActivityStopped {
+                id: stop.id,
+                activity_type: activity_type_name(&act.activity).to_owned(),
+                package_name: act.package_name.clone(),
+                duration_ms: millis(act.start.elapsed().unwrap_or_default()),
+            }],
+            None => Vec::new(),
+        },
+        Some(JSONMessage::Result(res)) => match &res.result {
+            ActivityResult::SetPhase(phase) => vec![Event::PhaseChanged {
+                id,
+                package_name: package_name(),
+                phase: phase.to_owned(),
+            }],
+            ActivityResult::Progress(progress) => vec![Event::Progress {
+                id,
+                package_name: package_name(),
+                done: progress.done,
+                expected: progress.expected,
+                running: progress.running,
+                failed: progress.failed,
+            }],
+            ActivityResult::BuildLogLine(line) | ActivityResult::PostBuildLogLine(line) => {
+                vec![Event::LogLine {
+                    id,
+                    package_name: package_name(),
+                    line: strip_ansi_escapes::strip_str(line),
+                }]
+            }
+            _ => Vec::new(),
+        },
+        Some(JSONMessage::Message(message)) => {
+            let text = strip_ansi_escapes::strip_str(&message.msg);
+            match message.level {
+                Verbosity::Error => {
+                    let error = parse_nix_error(&text);
+                    vec![Event::Error {
+                        summary: error.summary(),
+                        error,
+                    }]
+                }
+                level => vec![Event::Message {
+                    level: level_name(&level),
+                    package_name: package_name(),
+                    text,
+                }],
+            }
+        }
+        None => Vec::new(),
+    }
+}
+
+pub fn run_finished(state: &CommandState, status: &ExitStatus) -> Event {
+    let end = state.end.unwrap_or(SystemTime::now());
+    Event::RunFinished {
+        success: status.success(),
+        exit_code: status.code(),
+        duration_ms: millis(end.duration_since(state.start).unwrap_or_default()),
+        run_dir: state.run_dir.clone(),
+    }
+}
+
+/// Writes events as NDJSON to stdout, one flushed line per event.
+pub fn emit(event: Event) {
+    let record = EventRecord {
+        version: EVENT_SCHEMA_VERSION,
+        time: millis(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        ),
+        event,
+    };
+    let mut out = io::stdout().lock();
+    match serde_json::to_string(&record) {
+        Ok(line) => {
+            let _ = writeln!(out, "{}", line);
+            let _ = out.flush();
+        }
+        Err(err) => log::warn!("unable to serialize event: {}", err),
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::nix_logs::helpers::print_human;
+
+use super::format::decode_base64;
+
+const NIX32_ALPHABET: &[u8] = b"0123456789abcdfghijklmnpqrsvwxyz";
+const FAKE_HASH_NAMES: [&str; 3] = ["lib.fakeHash", "lib.fakeSha256", "lib.fakeSha512"];
+const MAX_FILES: usize = 10_000;
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct HashLocation {
+    pub file: PathBuf,
+    pub line: usize,
+    pub old: String,
+    pub new: String,
+}
+
+/// Encodes a digest the way `nix hash to-base32` does.
+fn encode_nix32(bytes: &[u8]) -> String {
+    let len = (bytes.len() * 8 - 1) / 5 + 1;
+    (0..len)
+        .rev()
+        .map(|n| {
+            let b = n * 5;
+            let (i, j) = (b / 8, b % 8);
+            let low = (bytes[i] as u16) >> j;
+            let high = match bytes.get(i + 1) {
+                Some(next) => (*next as u16) << (8 - j),
+                None => 0,
+            };
+            NIX32_ALPHABET[((low | high) & 0x1f) as usize] as char
+        })
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Every spelling of an SRI hash that may appear in a Nix expression, paired with
+/// the spelling of the replacement in the same encoding.
+fn hash_spellings(specified: &str, got: &str) -> Vec<(String, String)> {
+    let mut spellings = vec![(specified.to_owned(), got.to_owned())];
+    let decoded = |sri: &str| sri.split_once('-').and_then(|(_, b64)| decode_base64(b64));
+    if let (Some(old), Some(new)) = (decoded(specified), decoded(got)) {
+        if !old.is_empty() && !new.is_empty() {
+            spellings.push((encode_nix32(&old), encode_nix32(&new)));
+            spellings.push((encode_hex(&old), encode_hex(&new)));
+            if old.iter().all(|b| *b == 0) {
+                for name in FAKE_HASH_NAMES {
+                    spellings.push((name.to_owned(), format!("\"{}\"", got)));
+                }
+                spellings.push((String::from("\"\""), format!("\"{}\"", got)));
+            }
+        }
+    }
+    spellings
+}
+
+fn nix_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        if files.len() >= MAX_FILES {
+            return;
+        }
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        match entry.file_type() {
+            Ok(t)
+                if t.is_dir()
+                    && !name.starts_with('.')
+                    && name != "target"
+                    && name != "node_modules" =>
+            {
+                nix_files(&path, files)
+            }
+            Ok(t) if t.is_file() && name.ends_with(".nix") => files.push(path),
+            _ => {}
+        }
+    }
+}
+
+fn is_hash_attribute(line: &str, old: &str) -> bool {
+    // empty strings and fake hashes only count when they are assigned to a hash attribute
+    let old_is_generic = old == "\"\"" || FAKE_HASH_NAMES.contains(&old);
+    !old_is_generic
+        || [
+            "hash",
+            "sha256",
+            "sha512",
+            "outputHash",
+            "vendorHash",
+            "cargoHash",
+        ]
+        .iter()
+        .any(|attr| line.contains(&format!("{} = {}", attr, old)))
+}
+
+pub fn locate_hash(root: &Path, specified: &str, got: &str) -> Vec<HashLocation> {
+    let spellings = hash_spellings(specified, got);
+    let mut files = Vec::new();
+    nix_files(root, &mut files);
+    let mut locations = Vec::new();
+    for file in files {
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        for (n, line) in content.lines().enumerate() {
+            if let Some((old, new)) = spellings
+                .iter()
+                .find(|(old, _)| line.contains(old.as_str()) && is_hash_attribute(line, old))
+            {
+                locations.push(HashLocation {
+                    file: file.clone(),
+                    line: n + 1,
+                    old: old.to_owned(),
+                    new: new.to_owned(),
+                });
+            }
+        }
+    }
+    locations
+}
+
+pub fn print_hash_fix(drv_path: &str, specified: &str, got: &str) {
+    log::info!("  derivation: {}", drv_path);
+    log::info!("  correct hash: {}", got);
+    let root = std::env::current_dir().unwrap_or(PathBuf::from("."));
+    for location in locate_hash(&root, specified, got) {
+        let file = location
+            .file
+            .strip_prefix(&root)
+            .unwrap_or(&location.file)
+            .display()
+            .to_string();
+        log::info!(
+            "  declared at {}:{}, to update it run:",
+            file,
+            location.line
+        );
+        // printed directly since the log formatter strips quotes
+        print_human(format!(
+            "    sed -i '{}s|{}|{}|' {}",
+            location.line, location.old, location.new, file
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SRI hashes of the empty string and of "hello\n", with `nix hash to-base32` of them.
+    const KNOWN_HASHES: [(&str, &str); 5] = [
+        (
+            "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+            "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73",
+        ),
+        (
+            "sha256-WJG1tSLV3whtD/CxEPvZ0hu0/HFjrzTQgoai6Eb2vgM=",
+            "00xyyr3fi8l6hb839bv3f7yb86yjv7xi1cgh1xnhipym4asvb4aq",
+        ),
+        (
+            "sha1-2jmj7l5rSw0yVb/vlWAYkK/YBwk=",
+            "143xibwh31h9bvxzalr0sjvbbvpa6ffs",
+        ),
+        (
+            "sha512-z4PhNX7vuL3xVChQ1m2AB9Yg5AULVxXcg/SpIdNs6c5H0NE8XYXysP+DGNKHfuwvY7kxvUdBeoGlODJ6+SfaPg==",
+            "0zdl9zrg8r3i9c1g90lgg9ip5ijzv3yhz91i0zzn3r8ap9ws784gkp9dk9j3aglhgf1amqb0pj21mh7h1nxcl18akqvvf7ggqsy30yg",
+        ),
+        (
+            "sha512-58IrmUxZ2c8rSOVJseJGZmNgRZMNPafBrLKZ0cO3+TH5Sq5B7dosKyB6NuEPi8uNRSI+VIePWzFufOO2vAGWKQ==",
+            "0lrc0dwnvipqviibf7qfm1y492qvjwb1zhkcyi05cndmva1mr5gjcgrnz1x36djmk0sfg8djd2n0qv68vib2jg590mwznar9jcjphp7",
+        ),
+    ];
+
+    fn digest(sri: &str) -> Vec<u8> {
+        decode_base64(sri.split_once('-').unwrap().1).unwrap()
+    }
+
+    /// The inverse of `encode_nix32`, as done by `nix hash to-sri`.
+    fn decode_nix32(input: &str, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; len];
+        for (n, c) in input.bytes().rev().enumerate() {
+            let digit = NIX32_ALPHABET.iter().position(|a| *a == c).unwrap() as u16;
+            let b = n * 5;
+            let (i, j) = (b / 8, b % 8);
+            bytes[i] |= (digit << j) as u8;
+            if let Some(next) = bytes.get_mut(i + 1) {
+                *next |= (digit >> (8 - j)) as u8;
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn base64_to_nix32() {
+        for (sri, nix32) in KNOWN_HASHES {
+            assert_eq!(encode_nix32(&digest(sri)), nix32, "{}", sri);
+        }
+    }
+
+    #[test]
+    fn nix32_round_trip() {
+        for (sri, nix32) in KNOWN_HASHES {
+            let bytes = digest(sri);
+            assert_eq!(decode_nix32(nix32, bytes.len()), bytes, "{}", sri);
+            assert_eq!(decode_nix32(&encode_nix32(&bytes), bytes.len()), bytes);
+        }
+    }
+
+    #[test]
+    fn base64_to_hex() {
+        assert_eq!(
+            encode_hex(&digest(KNOWN_HASHES[0].0)),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            encode_hex(&digest(KNOWN_HASHES[2].0)),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+    }
+
+    #[test]
+    fn digest_lengths() {
+        assert_eq!(digest(KNOWN_HASHES[0].0).len(), 32);
+        assert_eq!(digest(KNOWN_HASHES[2].0).len(), 20);
+        assert_eq!(digest(KNOWN_HASHES[3].0).len(), 64);
+    }
+
+    #[test]
+    fn invalid_base64() {
+        assert_eq!(decode_base64("not-base64!"), None);
+        assert_eq!(decode_base64(""), Some(Vec::new()));
+    }
+
+    #[test]
+    fn spellings_of_a_fake_hash() {
+        let fake = "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+        let (got, got_nix32) = KNOWN_HASHES[1];
+        let spellings = hash_spellings(fake, got);
+        assert_eq!(spellings[0], (fake.to_owned(), got.to_owned()));
+        assert_eq!(spellings[1], ("0".repeat(52), got_nix32.to_owned()));
+        assert_eq!(spellings[2], ("0".repeat(64), encode_hex(&digest(got))));
+        assert!(spellings.contains(&("lib.fakeHash".to_owned(), format!("\"{}\"", got))));
+        assert!(spellings.contains(&("\"\"".to_owned(), format!("\"{}\"", got))));
+    }
+
+    #[test]
+    fn spellings_of_a_real_hash() {
+        let (specified, specified_nix32) = KNOWN_HASHES[0];
+        let (got, got_nix32) = KNOWN_HASHES[1];
+        let spellings = hash_spellings(specified, got);
+        assert_eq!(spellings.len(), 3);
+        assert_eq!(
+            spellings[1],
+            (specified_nix32.to_owned(), got_nix32.to_owned())
+        );
+    }
+}
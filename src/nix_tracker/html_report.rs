@@ -0,0 +1,369 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::nix_history::log_store::{log_tail, read_index};
+
+use super::{
+    format::{format_duration, format_size},
+    junit::xml_escape,
+    types::{JSONCommandState, JSONPhase},
+};
+
+/// Keeps the page small enough to attach to a CI job.
+const MAX_LOG_LINES: usize = 1000;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 small { font-weight: normal; color: #666; font-size: 0.6em; }
+.stats td { padding: 0.2em 1em 0.2em 0; }
+table.data { border-collapse: collapse; width: 100%; }
+table.data th, table.data td { text-align: left; padding: 0.2em 0.6em; border-bottom: 1px solid #ddd; }
+table.data th { cursor: pointer; background: #f4f4f4; }
+.gantt { position: relative; }
+.row { display: flex; align-items: center; height: 1.2em; font-size: 0.8em; }
+.label { width: 20em; overflow: hidden; white-space: nowrap; text-overflow: ellipsis; }
+.track { position: relative; flex: 1; height: 0.9em; background: #fafafa; }
+.bar { position: absolute; height: 100%; min-width: 1px; display: flex; }
+.bar span { height: 100%; }
+.build { background: #4e79a7; }
+.substitute { background: #59a14f; }
+.file_transfer { background: #9c755f; }
+.evaluation { background: #edc948; }
+.failed { background: #e15759; }
+pre { background: #f8f8f8; padding: 0.5em; overflow-x: auto; font-size: 0.8em; }
+.errors li { color: #b00; white-space: pre-wrap; }
+";
+
+const SCRIPT: &str = "
+document.querySelectorAll('table.data').forEach(function (table) {
+  table.querySelectorAll('th').forEach(function (th, col) {
+    th.addEventListener('click', function () {
+      var body = table.tBodies[0];
+      var asc = th.dataset.order !== 'asc';
+      th.dataset.order = asc ? 'asc' : 'desc';
+      var rows = Array.from(body.rows);
+      rows.sort(function (a, b) {
+        var x = a.cells[col].dataset.sort, y = b.cells[col].dataset.sort;
+        var d = (isNaN(x) || isNaN(y)) ? x.localeCompare(y) : x - y;
+        return asc ? d : -d;
+      });
+      rows.forEach(function (r) { body.appendChild(r); });
+    });
+  });
+});
+";
+
+const PHASE_COLOURS: [&str; 8] = [
+    "#4e79a7", "#f28e2b", "#76b7b2", "#ff9da7", "#af7aa1", "#edc948", "#bab0ac", "#59a14f",
+];
+
+struct Span<'a> {
+    kind: &'static str,
+    name: &'a str,
+    start: SystemTime,
+    end: SystemTime,
+    size: Option<i64>,
+    detail: &'a str,
+    phases: &'a [JSONPhase],
+    failed: bool,
+}
+
+fn since(start: SystemTime, t: SystemTime) -> Duration {
+    t.duration_since(start).unwrap_or_default()
+}
+
+fn spans(state: &JSONCommandState) -> Vec<Span<'_>> {
+    let failed: Vec<&str> = state.errors.iter().filter_map(|e| e.drv_path()).collect();
+    let mut spans: Vec<Span> = Vec::new();
+    for b in state.act_build.iter() {
+        spans.push(Span {
+            kind: "build",
+            name: &b.package_name,
+            start: b.start,
+            end: b.end,
+            size: None,
+            detail: &b.host,
+            phases: &b.phases,
+            failed: failed.contains(&b.store_path.as_str()),
+        });
+    }
+    for s in state.act_substitute.iter() {
+        spans.push(Span {
+            kind: "substitute",
+            name: &s.package_name,
+            start: s.start,
+            end: s.end,
+            size: s.size,
+            detail: &s.from,
+            phases: &[],
+            failed: false,
+        });
+    }
+    for f in state.act_file_transfer.iter() {
+        spans.push(Span {
+            kind: "file_transfer",
+            name: &f.file,
+            start: f.start,
+            end: f.end,
+            size: None,
+            detail: "",
+            phases: &[],
+            failed: false,
+        });
+    }
+    spans.sort_by_key(|s| s.start);
+    spans
+}
+
+struct Scale {
+    start: SystemTime,
+    total: f64,
+}
+
+impl Scale {
+    fn percent(&self, t: SystemTime) -> f64 {
+        100.0 * since(self.start, t).as_secs_f64() / self.total
+    }
+}
+
+fn bar(html: &mut String, scale: &Scale, class: &str, start: SystemTime, end: SystemTime) {
+    let left = scale.percent(start);
+    let _ = write!(
+        html,
+        "<div class=\"bar {}\" style=\"left:{:.3}%;width:{:.3}%\"",
+        class,
+        left,
+        (scale.percent(end) - left).max(0.0)
+    );
+}
+
+fn timeline(
+    html: &mut String,
+    state: &JSONCommandState,
+    spans: &[Span],
+    colours: &HashMap<String, &str>,
+) {
+    let scale = Scale {
+        start: state.start,
+        total: since(state.start, state.end).as_secs_f64().max(0.001),
+    };
+    html.push_str("<h2>Timeline</h2>\n<div class=\"gantt\">\n");
+    if let Some(eval) = &state.eval {
+        html.push_str(
+            "<div class=\"row\"><div class=\"label\">evaluation</div><div class=\"track\">",
+        );
+        bar(
+            html,
+            &scale,
+            "evaluation",
+            eval.start,
+            eval.start + eval.duration,
+        );
+        let _ = writeln!(
+            html,
+            " title=\"evaluation {}\"></div></div></div>",
+            format_duration(eval.duration)
+        );
+    }
+    for span in spans {
+        let _ = write!(
+            html,
+            "<div class=\"row\"><div class=\"label\" title=\"{0}\">{0}</div><div class=\"track\">",
+            xml_escape(span.name)
+        );
+        let class = if span.failed { "failed" } else { span.kind };
+        bar(html, &scale, class, span.start, span.end);
+        let _ = write!(
+            html,
+            " title=\"{} {} {}\">",
+            span.kind,
+            xml_escape(span.name),
+            format_duration(since(span.start, span.end))
+        );
+        let total = since(span.start, span.end).as_secs_f64().max(0.001);
+        for phase in span.phases {
+            let _ = write!(
+                html,
+                "<span style=\"width:{:.3}%;background:{}\" title=\"{} {}\"></span>",
+                100.0 * since(phase.start, phase.end).as_secs_f64() / total,
+                colours.get(&phase.name).unwrap_or(&"#888"),
+                xml_escape(&phase.name),
+                format_duration(since(phase.start, phase.end))
+            );
+        }
+        html.push_str("</div></div></div>\n");
+    }
+    html.push_str("</div>\n");
+}
+
+fn activity_table(html: &mut String, state: &JSONCommandState, spans: &[Span]) {
+    html.push_str(
+        "<h2>Builds and substitutions</h2>\n<table class=\"data\"><thead><tr>\
+         <th>kind</th><th>derivation</th><th>start</th><th>duration</th><th>size</th><th>host / cache</th>\
+         </tr></thead><tbody>\n",
+    );
+    for span in spans.iter().filter(|s| s.kind != "file_transfer") {
+        let start = since(state.start, span.start);
+        let duration = since(span.start, span.end);
+        let _ = writeln!(
+            html,
+            "<tr><td data-sort=\"{kind}\">{kind}{failed}</td><td data-sort=\"{name}\">{name}</td>\
+             <td data-sort=\"{}\">{}</td><td data-sort=\"{}\">{}</td><td data-sort=\"{}\">{}</td>\
+             <td data-sort=\"{detail}\">{detail}</td></tr>",
+            start.as_millis(),
+            format_duration(start),
+            duration.as_millis(),
+            format_duration(duration),
+            span.size.unwrap_or(-1),
+            span.size.map_or(String::from("-"), format_size),
+            kind = span.kind,
+            failed = if span.failed { " (failed)" } else { "" },
+            name = xml_escape(span.name),
+            detail = xml_escape(span.detail),
+        );
+    }
+    html.push_str("</tbody></table>\n");
+}
+
+fn phase_breakdown(html: &mut String, state: &JSONCommandState, colours: &HashMap<String, &str>) {
+    let mut totals: Vec<(String, Duration, usize)> = Vec::new();
+    for phase in state.act_build.iter().flat_map(|b| b.phases.iter()) {
+        let duration = since(phase.start, phase.end);
+        match totals.iter_mut().find(|(name, _, _)| *name == phase.name) {
+            Some((_, total, count)) => {
+                *total += duration;
+                *count += 1;
+            }
+            None => totals.push((phase.name.to_owned(), duration, 1)),
+        }
+    }
+    if totals.is_empty() {
+        return;
+    }
+    totals.sort_by_key(|(_, total, _)| std::cmp::Reverse(*total));
+    html.push_str(
+        "<h2>Phases</h2>\n<table class=\"data\"><thead><tr>\
+         <th>phase</th><th>builds</th><th>total</th><th>mean</th></tr></thead><tbody>\n",
+    );
+    for (name, total, count) in totals {
+        let mean = total / count as u32;
+        let _ = writeln!(
+            html,
+            "<tr><td data-sort=\"{name}\"><span style=\"color:{}\">&#9632;</span> {name}</td>\
+             <td data-sort=\"{count}\">{count}</td><td data-sort=\"{}\">{}</td><td data-sort=\"{}\">{}</td></tr>",
+            colours.get(&name).unwrap_or(&"#888"),
+            total.as_millis(),
+            format_duration(total),
+            mean.as_millis(),
+            format_duration(mean),
+            name = xml_escape(&name),
+            count = count,
+        );
+    }
+    html.push_str("</tbody></table>\n");
+}
+
+fn logs(html: &mut String, run_dir: &Path) {
+    let index = read_index(run_dir);
+    if index.is_empty() {
+        return;
+    }
+    html.push_str("<h2>Build logs</h2>\n");
+    for entry in index.iter() {
+        let lines = log_tail(run_dir, &entry.drv_path, MAX_LOG_LINES);
+        let _ = write!(
+            html,
+            "<details><summary>{} <small>({} lines{})</small></summary><pre>",
+            xml_escape(&entry.package_name),
+            entry.lines,
+            if entry.lines > MAX_LOG_LINES {
+                format!(", last {} shown", MAX_LOG_LINES)
+            } else {
+                String::new()
+            }
+        );
+        html.push_str(&xml_escape(&lines.join("\n")));
+        html.push_str("</pre></details>\n");
+    }
+}
+
+/// A self-contained page with the timeline, durations, phases, cache hits and logs of a run.
+pub fn html_report(state: &JSONCommandState, run_dir: Option<&Path>) -> String {
+    let spans = spans(state);
+    let mut colours: HashMap<String, &str> = HashMap::new();
+    for phase in state.act_build.iter().flat_map(|b| b.phases.iter()) {
+        let next = PHASE_COLOURS[colours.len() % PHASE_COLOURS.len()];
+        colours.entry(phase.name.to_owned()).or_insert(next);
+    }
+    let built = state.act_build.len();
+    let substituted = state.act_substitute.len();
+    let downloaded: i64 = state.act_substitute.iter().filter_map(|s| s.size).sum();
+    let started: DateTime<Utc> = state.start.into();
+
+    let mut html = String::from("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">");
+    let _ = writeln!(
+        html,
+        "<title>nixv report {}</title><style>{}</style></head><body>",
+        started.format("%Y-%m-%d %H:%M:%S"),
+        STYLE
+    );
+    let _ = writeln!(
+        html,
+        "<h1>nixv report <small>{} UTC</small></h1>",
+        started.format("%Y-%m-%d %H:%M:%S")
+    );
+    html.push_str("<table class=\"stats\">\n");
+    let _ = writeln!(
+        html,
+        "<tr><td>total time</td><td>{}</td></tr>",
+        format_duration(since(state.start, state.end))
+    );
+    if let Some(eval) = &state.eval {
+        let _ = writeln!(
+            html,
+            "<tr><td>evaluation</td><td>{}</td></tr>",
+            format_duration(eval.duration)
+        );
+    }
+    let _ = writeln!(html, "<tr><td>built</td><td>{}</td></tr>", built);
+    let _ = writeln!(
+        html,
+        "<tr><td>substituted</td><td>{} ({})</td></tr>",
+        substituted,
+        format_size(downloaded)
+    );
+    if built + substituted > 0 {
+        let _ = writeln!(
+            html,
+            "<tr><td>cache hit rate</td><td>{:.0}%</td></tr>",
+            100.0 * substituted as f64 / (built + substituted) as f64
+        );
+    }
+    let _ = writeln!(
+        html,
+        "<tr><td>errors</td><td>{}</td></tr>",
+        state.errors.len()
+    );
+    html.push_str("</table>\n");
+    if !state.errors.is_empty() {
+        html.push_str("<h2>Errors</h2>\n<ul class=\"errors\">\n");
+        for err in state.errors.iter() {
+            let _ = writeln!(html, "<li>{}</li>", xml_escape(&err.summary()));
+        }
+        html.push_str("</ul>\n");
+    }
+    timeline(&mut html, state, &spans, &colours);
+    activity_table(&mut html, state, &spans);
+    phase_breakdown(&mut html, state, &colours);
+    if let Some(run_dir) = run_dir {
+        logs(&mut html, run_dir);
+    }
+    let _ = writeln!(html, "<script>{}</script>\n</body></html>", SCRIPT);
+    html
+}
@@ -0,0 +1,203 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{Error, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{nix_history::log_store::log_tail, nix_logs::errors::NixError};
+
+use super::{format::seconds, types::JSONCommandState};
+
+const LOG_TAIL_LINES: usize = 50;
+
+struct TestCase {
+    classname: &'static str,
+    name: String,
+    time: f64,
+    /// message, type and body of the failure
+    failure: Option<(String, &'static str, String)>,
+    skipped: Option<String>,
+}
+
+pub(crate) fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // not allowed in XML 1.0, mostly left over escape codes
+            c if (c as u32) < 0x20 && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn error_kind(err: &NixError) -> &'static str {
+    match err {
+        NixError::BuilderFailed { .. } => "BuilderFailed",
+        NixError::HashMismatch { .. } => "HashMismatch",
+        NixError::DependencyFailed { .. } => "DependencyFailed",
+        NixError::NotDeterministic { .. } => "NotDeterministic",
+        NixError::Evaluation { .. } => "Evaluation",
+        NixError::Other(_) => "Other",
+    }
+}
+
+/// The failure body, the tail of the build log from the error or else from the run's log store.
+fn failure_text(err: &NixError, run_dir: Option<&Path>) -> String {
+    match err {
+        NixError::BuilderFailed {
+            drv_path,
+            log_tail: tail,
+            ..
+        } => match (tail.is_empty(), run_dir) {
+            (true, Some(run_dir)) => log_tail(run_dir, drv_path, LOG_TAIL_LINES).join("\n"),
+            _ => tail.join("\n"),
+        },
+        NixError::Evaluation { trace, .. } => trace
+            .iter()
+            .map(|frame| match &frame.position {
+                Some(p) => format!(
+                    "… {} at {}:{}:{}",
+                    frame.description, p.file, p.line, p.column
+                ),
+                None => format!("… {}", frame.description),
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+        NixError::Other(msg) => msg.to_owned(),
+        _ => String::new(),
+    }
+}
+
+fn test_cases(state: &JSONCommandState, run_dir: Option<&Path>) -> Vec<TestCase> {
+    let mut cases: Vec<TestCase> = Vec::new();
+    let mut reported = vec![false; state.errors.len()];
+    for build in state.act_build.iter() {
+        let failure = state
+            .errors
+            .iter()
+            .position(|err| err.drv_path() == Some(build.store_path.as_str()))
+            .map(|i| {
+                reported[i] = true;
+                let err = &state.errors[i];
+                (err.summary(), error_kind(err), failure_text(err, run_dir))
+            });
+        cases.push(TestCase {
+            classname: "build",
+            name: build.package_name.to_owned(),
+            time: seconds(build.start, build.end),
+            failure,
+            skipped: None,
+        });
+    }
+    for substitute in state.act_substitute.iter() {
+        cases.push(TestCase {
+            classname: "substitute",
+            name: substitute.package_name.to_owned(),
+            time: seconds(substitute.start, substitute.end),
+            failure: None,
+            skipped: Some(format!("substituted from {}", substitute.from)),
+        });
+    }
+    // errors without a build of their own, evaluation errors or failed dependencies
+    for (err, _) in state
+        .errors
+        .iter()
+        .zip(reported)
+        .filter(|(_, reported)| !reported)
+    {
+        let name = match err {
+            NixError::BuilderFailed { package_name, .. }
+            | NixError::HashMismatch { package_name, .. }
+            | NixError::DependencyFailed { package_name, .. }
+            | NixError::NotDeterministic { package_name, .. } => package_name.to_owned(),
+            NixError::Evaluation { .. } | NixError::Other(_) => String::from("evaluation"),
+        };
+        let classname = match err.drv_path() {
+            Some(_) => "build",
+            None => "evaluation",
+        };
+        cases.push(TestCase {
+            classname,
+            name,
+            time: 0.0,
+            failure: Some((err.summary(), error_kind(err), failure_text(err, run_dir))),
+            skipped: None,
+        });
+    }
+    cases
+}
+
+/// A JUnit report with one testcase per derivation built or substituted in the run.
+pub fn junit_report(state: &JSONCommandState, run_dir: Option<&Path>) -> String {
+    let cases = test_cases(state, run_dir);
+    let failures = cases.iter().filter(|c| c.failure.is_some()).count();
+    let skipped = cases.iter().filter(|c| c.skipped.is_some()).count();
+    let time = seconds(state.start, state.end);
+    let timestamp: DateTime<Utc> = state.start.into();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"nixv\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+        cases.len(),
+        failures,
+        skipped,
+        time
+    );
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"nix\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{:.3}\" timestamp=\"{}\">",
+        cases.len(),
+        failures,
+        skipped,
+        time,
+        timestamp.format("%Y-%m-%dT%H:%M:%S")
+    );
+    for case in cases.iter() {
+        let _ = write!(
+            xml,
+            "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+            case.classname,
+            xml_escape(&case.name),
+            case.time
+        );
+        match (&case.failure, &case.skipped) {
+            (Some((message, kind, text)), _) => {
+                let _ = writeln!(
+                    xml,
+                    ">\n      <failure message=\"{}\" type=\"{}\">{}</failure>\n    </testcase>",
+                    xml_escape(message),
+                    kind,
+                    xml_escape(text)
+                );
+            }
+            (None, Some(message)) => {
+                let _ = writeln!(
+                    xml,
+                    ">\n      <skipped message=\"{}\"/>\n    </testcase>",
+                    xml_escape(message)
+                );
+            }
+            (None, None) => xml.push_str("/>\n"),
+        }
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+pub fn write_junit_report(
+    file_name: &Path,
+    state: &JSONCommandState,
+    run_dir: Option<&Path>,
+) -> Result<(), Error> {
+    let mut file = File::create(file_name)?;
+    file.write_all(junit_report(state, run_dir).as_bytes())
+}
@@ -0,0 +1,265 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    io::{Error, Read, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::nix_logs::types::Activity;
+
+use super::{format::seconds, types::CommandState};
+
+/// How often the textfile and the served metrics follow the run.
+const UPDATE_INTERVAL: Duration = Duration::from_secs(5);
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Metrics {
+    pub builds: u64,
+    pub substitutions: u64,
+    pub downloaded_bytes: i64,
+    pub phase_seconds: BTreeMap<String, f64>,
+    pub failures: u64,
+    pub wall_time_seconds: f64,
+    pub running: bool,
+    /// start of the run and of the phases of running builds, the time spent in them keeps
+    /// growing between two updates of the state
+    start: Option<SystemTime>,
+    running_phases: Vec<(String, SystemTime)>,
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Metrics {
+    pub fn from_state(state: &CommandState) -> Metrics {
+        let now = SystemTime::now();
+        let running = state.end.is_none();
+        let mut metrics = Metrics {
+            failures: state.errors.len() as u64,
+            wall_time_seconds: seconds(state.start, state.end.unwrap_or(now)),
+            running,
+            start: Some(state.start),
+            ..Metrics::default()
+        };
+        for act in state.activity.values() {
+            match &act.activity {
+                Activity::ActBuild(..) => {
+                    metrics.builds += 1;
+                    for (i, (phase, start)) in act.phases.iter().enumerate() {
+                        match (act.phases.get(i + 1).map(|(_, t)| *t), act.end) {
+                            (Some(phase_end), _) | (None, Some(phase_end)) => {
+                                *metrics.phase_seconds.entry(phase.to_owned()).or_default() +=
+                                    seconds(*start, phase_end)
+                            }
+                            (None, None) if running => {
+                                metrics.phase_seconds.entry(phase.to_owned()).or_default();
+                                metrics.running_phases.push((phase.to_owned(), *start));
+                            }
+                            (None, None) => {
+                                *metrics.phase_seconds.entry(phase.to_owned()).or_default() +=
+                                    seconds(*start, now)
+                            }
+                        }
+                    }
+                }
+                Activity::ActSubstitute(..) => metrics.substitutions += 1,
+                Activity::ActFileTransfer(_) => {
+                    metrics.downloaded_bytes += act.progress.map(|p| p.done).unwrap_or_default()
+                }
+                _ => {}
+            }
+        }
+        metrics
+    }
+
+    /// The metrics with the wall time and the phases still running brought up to `now`.
+    pub fn at(&self, now: SystemTime) -> Metrics {
+        let mut metrics = self.clone();
+        if !self.running {
+            return metrics;
+        }
+        if let Some(start) = self.start {
+            metrics.wall_time_seconds = seconds(start, now);
+        }
+        for (phase, start) in self.running_phases.iter() {
+            *metrics.phase_seconds.entry(phase.to_owned()).or_default() += seconds(*start, now);
+        }
+        metrics.running_phases.clear();
+        metrics
+    }
+
+    /// The OpenMetrics text exposition of the run.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let mut counter = |name: &str, help: &str, value: String| {
+            let _ = writeln!(text, "# TYPE nixv_{} counter", name);
+            if let Some(unit) = name.strip_prefix("downloaded_") {
+                let _ = writeln!(text, "# UNIT nixv_{} {}", name, unit);
+            }
+            let _ = writeln!(text, "# HELP nixv_{} {}", name, help);
+            let _ = writeln!(text, "nixv_{}_total {}", name, value);
+        };
+        counter("builds", "Derivations built.", self.builds.to_string());
+        counter(
+            "substitutions",
+            "Store paths substituted from a cache.",
+            self.substitutions.to_string(),
+        );
+        counter(
+            "downloaded_bytes",
+            "Bytes downloaded from caches.",
+            self.downloaded_bytes.to_string(),
+        );
+        counter(
+            "failures",
+            "Errors reported by nix.",
+            self.failures.to_string(),
+        );
+        let _ = writeln!(text, "# TYPE nixv_phase_seconds counter");
+        let _ = writeln!(text, "# UNIT nixv_phase_seconds seconds");
+        let _ = writeln!(
+            text,
+            "# HELP nixv_phase_seconds Time spent by builds in each phase."
+        );
+        for (phase, secs) in self.phase_seconds.iter() {
+            let _ = writeln!(
+                text,
+                "nixv_phase_seconds_total{{phase=\"{}\"}} {:.3}",
+                escape_label(phase),
+                secs
+            );
+        }
+        let _ = writeln!(text, "# TYPE nixv_wall_time_seconds gauge");
+        let _ = writeln!(text, "# UNIT nixv_wall_time_seconds seconds");
+        let _ = writeln!(
+            text,
+            "# HELP nixv_wall_time_seconds Time since the command started."
+        );
+        let _ = writeln!(text, "nixv_wall_time_seconds {:.3}", self.wall_time_seconds);
+        let _ = writeln!(text, "# TYPE nixv_running gauge");
+        let _ = writeln!(
+            text,
+            "# HELP nixv_running Whether the command is still running."
+        );
+        let _ = writeln!(text, "nixv_running {}", self.running as u8);
+        text.push_str("# EOF\n");
+        text
+    }
+}
+
+fn serve(listener: TcpListener, metrics: Arc<Mutex<Metrics>>) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        // every request gets the metrics, the request itself doesn't matter
+        let mut request = [0u8; 1024];
+        let _ = stream.read(&mut request);
+        let body = render_now(&metrics);
+        let _ = write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            CONTENT_TYPE,
+            body.len(),
+            body
+        );
+    }
+}
+
+fn render_now(metrics: &Mutex<Metrics>) -> String {
+    metrics
+        .lock()
+        .map(|m| m.at(SystemTime::now()).render())
+        .unwrap_or_default()
+}
+
+fn write_textfile(file: &Path, text: &str) {
+    // written aside and renamed, node_exporter never reads half a file
+    let tmp = file.with_extension("prom.tmp");
+    if let Err(err) = fs::write(&tmp, text).and_then(|_| fs::rename(&tmp, file)) {
+        log::warn!("unable to write metrics to {}: {}", file.display(), err);
+    }
+}
+
+/// Rewrites the textfile every [`UPDATE_INTERVAL`] until `stop` is dropped, so that the time
+/// based metrics follow silent builds too.
+fn refresh_textfile(file: PathBuf, metrics: Arc<Mutex<Metrics>>) -> (Sender<()>, JoinHandle<()>) {
+    let (stop, stopped) = channel::<()>();
+    let refresher = thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(UPDATE_INTERVAL) {
+            write_textfile(&file, &render_now(&metrics));
+        }
+    });
+    (stop, refresher)
+}
+
+/// Keeps a textfile for node_exporter and/or a local HTTP endpoint up to date during a run.
+pub struct MetricsExporter {
+    file: Option<PathBuf>,
+    metrics: Arc<Mutex<Metrics>>,
+    refresher: Option<(Sender<()>, JoinHandle<()>)>,
+    last_update: Option<Instant>,
+}
+
+impl MetricsExporter {
+    pub fn new(file: Option<PathBuf>, port: Option<u16>) -> Result<MetricsExporter, Error> {
+        let metrics = Arc::new(Mutex::new(Metrics::default()));
+        if let Some(port) = port {
+            let listener = TcpListener::bind(("127.0.0.1", port))?;
+            log::info!("serving metrics on http://127.0.0.1:{}/metrics", port);
+            let shared = metrics.clone();
+            thread::spawn(move || serve(listener, shared));
+        }
+        let refresher = file
+            .as_ref()
+            .map(|file| refresh_textfile(file.clone(), metrics.clone()));
+        Ok(MetricsExporter {
+            file,
+            metrics,
+            refresher,
+            last_update: None,
+        })
+    }
+
+    pub fn update(&mut self, state: &CommandState) {
+        let due = match self.last_update {
+            Some(last) => last.elapsed() >= UPDATE_INTERVAL,
+            None => true,
+        };
+        if due {
+            self.last_update = Some(Instant::now());
+            self.set(Metrics::from_state(state));
+        }
+    }
+
+    pub fn finish(mut self, state: &CommandState) {
+        if let Some((stop, refresher)) = self.refresher.take() {
+            drop(stop);
+            let _ = refresher.join();
+        }
+        self.set(Metrics::from_state(state));
+        if let Some(file) = &self.file {
+            write_textfile(file, &render_now(&self.metrics));
+        }
+    }
+
+    fn set(&self, metrics: Metrics) {
+        if let Ok(mut shared) = self.metrics.lock() {
+            *shared = metrics;
+        }
+    }
+}
@@ -1 +1,19 @@
+pub mod build_all;
+pub mod ci;
+pub mod closure;
+pub mod disposition;
+pub mod drv_diff;
+pub mod eta;
+pub mod eval;
+pub mod events;
+pub mod format;
+pub mod hash_mismatch;
+pub mod html_report;
+pub mod junit;
+pub mod metrics;
+pub mod monitor;
+pub mod otlp;
+pub mod regression;
+pub mod repro;
+pub mod tracker;
 pub mod types;
@@ -0,0 +1,396 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Build logs modified since then are listed as recent.
+const RECENT: Duration = Duration::from_secs(15 * 60);
+const MAX_RECENT: usize = 10;
+
+/// A build found through the temporary directory nix creates for it.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct RunningBuild {
+    pub name: String,
+    pub build_dir: PathBuf,
+    pub start: SystemTime,
+    /// the build user owning the directory, and the processes of the build
+    pub uid: u32,
+    pub processes: usize,
+    /// user and system time of the processes running now
+    pub cpu_seconds: f64,
+    /// share of one cpu used since the previous snapshot
+    pub cpu_percent: Option<f64>,
+    /// the busiest process of the build
+    pub command: Option<String>,
+}
+
+/// A build log of the daemon written to recently.
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Clone)]
+pub struct RecentLog {
+    pub name: String,
+    pub drv_path: String,
+    pub modified: SystemTime,
+}
+
+/// What the nix builds of the whole machine look like at one point in time.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct MachineSnapshot {
+    pub time: SystemTime,
+    pub load: Option<(f64, f64, f64)>,
+    pub cpus: usize,
+    /// nix-daemon processes serving a client
+    pub daemon_workers: usize,
+    pub running: Vec<RunningBuild>,
+    pub recent: Vec<RecentLog>,
+    /// what the next snapshot needs not to look at the logs again
+    #[serde(skip)]
+    logs: LogScan,
+}
+
+/// The log directories as of a snapshot, and the logs in them modified recently.
+#[derive(Debug, Default, PartialEq, Clone)]
+struct LogScan {
+    dirs: HashMap<PathBuf, SystemTime>,
+    recent: Vec<(PathBuf, RecentLog)>,
+}
+
+/// Where the sandbox mounts the build directory, `sandbox-build-dir` in nix.
+const SANDBOX_BUILD_DIR: &str = "/build";
+
+struct Process {
+    uid: u32,
+    comm: String,
+    cpu_seconds: f64,
+    parent: i32,
+    /// unknown for the processes of other users unless nixv runs as root
+    cwd: Option<PathBuf>,
+}
+
+/// Kernel clock ticks per second, the unit of the cpu times in `/proc/<pid>/stat`.
+fn clock_ticks() -> f64 {
+    static CLOCK_TICKS: OnceLock<f64> = OnceLock::new();
+    *CLOCK_TICKS.get_or_init(|| {
+        // SAFETY: sysconf only reads a configuration value
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        if ticks > 0 {
+            ticks as f64
+        } else {
+            100.0
+        }
+    })
+}
+
+fn read_process(dir: &Path) -> Option<Process> {
+    let uid = fs::metadata(dir).ok()?.uid();
+    let stat = fs::read_to_string(dir.join("stat")).ok()?;
+    // the command name is in parentheses and may contain spaces
+    let (head, rest) = stat.rsplit_once(')')?;
+    let comm = head.split_once('(')?.1.to_owned();
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let ticks = |i: usize| fields.get(i).and_then(|f| f.parse::<f64>().ok());
+    Some(Process {
+        uid,
+        comm,
+        cpu_seconds: (ticks(11)? + ticks(12)?) / clock_ticks(),
+        parent: fields.get(1)?.parse().ok()?,
+        cwd: fs::read_link(dir.join("cwd")).ok(),
+    })
+}
+
+fn processes() -> HashMap<i32, Process> {
+    match fs::read_dir("/proc") {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|e| {
+                let pid = e.file_name().to_str()?.parse().ok()?;
+                Some((pid, read_process(&e.path())?))
+            })
+            .collect(),
+        Err(_) => HashMap::new(),
+    }
+}
+
+/// Where nix creates build directories, the temporary directory of the daemon
+/// or the `build-dir` of recent versions.
+fn build_dir_roots() -> Vec<PathBuf> {
+    let mut roots = vec![
+        env::temp_dir(),
+        PathBuf::from("/tmp"),
+        PathBuf::from("/nix/var/nix/builds"),
+    ];
+    roots.dedup();
+    roots
+}
+
+/// `nix-build-hello-2.12.1.drv-0` is a build of `hello-2.12.1`.
+fn build_name(dir_name: &str) -> Option<String> {
+    let rest = dir_name.strip_prefix("nix-build-")?;
+    let (name, _) = rest.rsplit_once('-')?;
+    Some(name.strip_suffix(".drv").unwrap_or(name).to_owned())
+}
+
+fn log_dir() -> PathBuf {
+    match env::var("NIX_LOG_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from("/nix/var/log/nix"),
+    }
+    .join("drvs")
+}
+
+fn recent_log(prefix: &str, file: &Path, now: SystemTime) -> Option<RecentLog> {
+    let modified = fs::metadata(file).and_then(|m| m.modified()).ok()?;
+    if now.duration_since(modified).unwrap_or_default() > RECENT {
+        return None;
+    }
+    let file_name = file.file_name()?.to_string_lossy();
+    let drv = file_name.strip_suffix(".bz2").unwrap_or(&file_name);
+    let name = drv.split_once('-').map(|(_, name)| name).unwrap_or(drv);
+    Some(RecentLog {
+        name: name.strip_suffix(".drv").unwrap_or(name).to_owned(),
+        drv_path: format!("/nix/store/{}{}", prefix, drv),
+        modified,
+    })
+}
+
+/// Logs are stored as `drvs/<first 2 chars of the hash>/<rest of the drv file name>.bz2`.
+/// A directory whose mtime is the one of the previous scan got no new log, only the logs
+/// that were recent then are looked at again, the others are done being written.
+fn recent_logs(now: SystemTime, previous: &LogScan) -> LogScan {
+    let mut scan = LogScan::default();
+    let prefixes = match fs::read_dir(log_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return scan,
+    };
+    for prefix in prefixes.flatten() {
+        let dir = prefix.path();
+        let modified = match prefix.metadata().and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(_) => continue,
+        };
+        let prefix = prefix.file_name().to_string_lossy().to_string();
+        let files: Vec<PathBuf> = if previous.dirs.get(&dir) == Some(&modified) {
+            previous
+                .recent
+                .iter()
+                .filter(|(file, _)| file.parent() == Some(dir.as_path()))
+                .map(|(file, _)| file.to_owned())
+                .collect()
+        } else {
+            match fs::read_dir(&dir) {
+                Ok(files) => files.flatten().map(|file| file.path()).collect(),
+                Err(_) => continue,
+            }
+        };
+        for file in files {
+            if let Some(log) = recent_log(&prefix, &file, now) {
+                scan.recent.push((file, log));
+            }
+        }
+        scan.dirs.insert(dir, modified);
+    }
+    scan.recent
+        .sort_by_key(|(_, l)| std::cmp::Reverse(l.modified));
+    scan
+}
+
+/// The processes of a build: the ones working in its directory, or else the processes of its
+/// build user working in the sandbox. A build user runs one build at a time, so only its
+/// newest directory gets those, older ones are leftovers or kept with `--keep-failed`.
+fn build_processes<'a>(
+    processes: &'a HashMap<i32, Process>,
+    build_dir: &Path,
+    uid: u32,
+    newest: bool,
+) -> Vec<&'a Process> {
+    let in_dir: Vec<&Process> = processes
+        .values()
+        .filter(|p| p.cwd.as_ref().is_some_and(|cwd| cwd.starts_with(build_dir)))
+        .collect();
+    if !in_dir.is_empty() || uid == 0 || !newest {
+        return in_dir;
+    }
+    processes
+        .values()
+        .filter(|p| {
+            p.uid == uid
+                && p.cwd
+                    .as_ref()
+                    .map_or(true, |cwd| cwd.starts_with(SANDBOX_BUILD_DIR))
+        })
+        .collect()
+}
+
+fn running_builds(
+    processes: &HashMap<i32, Process>,
+    previous: Option<&MachineSnapshot>,
+) -> Vec<RunningBuild> {
+    let mut dirs = Vec::new();
+    let mut seen = HashSet::new();
+    for root in build_dir_roots() {
+        let entries = match fs::read_dir(&root) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let name = match build_name(&entry.file_name().to_string_lossy()) {
+                Some(name) => name,
+                None => continue,
+            };
+            let metadata = match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => metadata,
+                _ => continue,
+            };
+            if !seen.insert(entry.path()) {
+                continue;
+            }
+            let start = metadata
+                .created()
+                .or(metadata.modified())
+                .unwrap_or(SystemTime::now());
+            dirs.push((name, entry.path(), metadata.uid(), start));
+        }
+    }
+    let mut newest: HashMap<u32, SystemTime> = HashMap::new();
+    for (_, _, uid, start) in dirs.iter() {
+        let newest = newest.entry(*uid).or_insert(*start);
+        *newest = (*newest).max(*start);
+    }
+    let mut builds = Vec::new();
+    for (name, build_dir, uid, start) in dirs {
+        let owned = build_processes(processes, &build_dir, uid, newest.get(&uid) == Some(&start));
+        // a directory without processes is left over from a build that is over
+        if owned.is_empty() {
+            continue;
+        }
+        let cpu_seconds = owned.iter().fold(0.0, |cpu, p| cpu + p.cpu_seconds);
+        builds.push(RunningBuild {
+            name,
+            build_dir,
+            start,
+            uid,
+            processes: owned.len(),
+            cpu_seconds,
+            cpu_percent: None,
+            command: owned
+                .iter()
+                .max_by(|a, b| a.cpu_seconds.total_cmp(&b.cpu_seconds))
+                .map(|p| p.comm.to_owned()),
+        });
+    }
+    if let Some(previous) = previous {
+        let elapsed = SystemTime::now()
+            .duration_since(previous.time)
+            .unwrap_or_default()
+            .as_secs_f64();
+        for build in builds.iter_mut() {
+            let before = previous
+                .running
+                .iter()
+                .find(|b| b.build_dir == build.build_dir);
+            if let (Some(before), true) = (before, elapsed > 0.0) {
+                let used = (build.cpu_seconds - before.cpu_seconds).max(0.0);
+                build.cpu_percent = Some(100.0 * used / elapsed);
+            }
+        }
+    }
+    builds.sort_by_key(|b| b.start);
+    builds
+}
+
+fn load() -> Option<(f64, f64, f64)> {
+    let loadavg = fs::read_to_string("/proc/loadavg").ok()?;
+    let mut fields = loadavg.split_whitespace().map(|f| f.parse().ok());
+    Some((fields.next()??, fields.next()??, fields.next()??))
+}
+
+impl MachineSnapshot {
+    /// Looks at the build directories, the processes and the daemon logs, `previous`
+    /// gives the cpu usage since then.
+    pub fn take(previous: Option<&MachineSnapshot>) -> MachineSnapshot {
+        let processes = processes();
+        // the daemon forks a worker per client connection
+        let daemon_workers = processes
+            .values()
+            .filter(|p| {
+                p.comm == "nix-daemon"
+                    && processes
+                        .get(&p.parent)
+                        .is_some_and(|parent| parent.comm == "nix-daemon")
+            })
+            .count();
+        let now = SystemTime::now();
+        let logs = recent_logs(
+            now,
+            previous.map(|p| &p.logs).unwrap_or(&LogScan::default()),
+        );
+        MachineSnapshot {
+            running: running_builds(&processes, previous),
+            recent: logs
+                .recent
+                .iter()
+                .take(MAX_RECENT)
+                .map(|(_, log)| log.to_owned())
+                .collect(),
+            logs,
+            time: now,
+            load: load(),
+            cpus: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            daemon_workers,
+        }
+    }
+
+    /// Builds whose log was written to recently but that are not running anymore.
+    pub fn finished(&self) -> Vec<&RecentLog> {
+        self.recent
+            .iter()
+            .filter(|l| !self.running.iter().any(|b| b.name == l.name))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn build_name_strips_the_prefix_and_counter() {
+        assert_eq!(
+            build_name("nix-build-hello-2.12.1.drv-0"),
+            Some(String::from("hello-2.12.1"))
+        );
+        assert_eq!(
+            build_name("nix-build-source.drv-12"),
+            Some(String::from("source"))
+        );
+        assert_eq!(build_name("nix-shell-hello"), None);
+        assert_eq!(build_name("nix-build-"), None);
+    }
+
+    #[test]
+    fn read_process_parses_a_command_name_with_parentheses() {
+        let dir = env::temp_dir().join(format!("nixv-monitor-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("stat"),
+            "4321 (cc1 (gcc) x) R 4300 4321 4321 0 -1 4194560 100 0 0 0 250 50 0 0 20 0 1 0\n",
+        )
+        .unwrap();
+        let process = read_process(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let process = process.unwrap();
+        assert_eq!(process.comm, "cc1 (gcc) x");
+        assert_eq!(process.parent, 4300);
+        assert_eq!(process.cpu_seconds, 300.0 / clock_ticks());
+        assert_eq!(process.cwd, None);
+    }
+}
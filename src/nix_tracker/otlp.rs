@@ -0,0 +1,244 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs,
+    hash::{BuildHasher, Hasher},
+    io::{Error, ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::Path,
+    process::ExitStatus,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+
+use crate::nix_logs::types::Activity;
+
+use super::{events::activity_type_name, types::CommandState};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const SPAN_KIND_INTERNAL: i64 = 1;
+const STATUS_ERROR: i64 = 2;
+
+/// Span ids of one trace, random per run and stable per activity id.
+struct Ids {
+    hasher: RandomState,
+}
+
+impl Ids {
+    fn hash(&self, value: i64) -> u64 {
+        let mut hasher = self.hasher.build_hasher();
+        hasher.write_i64(value);
+        // all zero ids are invalid
+        hasher.finish().max(1)
+    }
+
+    fn trace_id(&self) -> String {
+        format!("{:016x}{:016x}", self.hash(-2), self.hash(-3))
+    }
+
+    fn span_id(&self, activity: Option<i64>) -> String {
+        format!("{:016x}", self.hash(activity.unwrap_or(-1)))
+    }
+}
+
+fn nanos(t: SystemTime) -> String {
+    t.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn int_attribute(key: &str, value: i64) -> Value {
+    json!({ "key": key, "value": { "intValue": value.to_string() } })
+}
+
+fn activity_attributes(activity: &Activity) -> Vec<Value> {
+    let mut attributes = vec![string_attribute(
+        "nix.activity.type",
+        activity_type_name(activity),
+    )];
+    match activity {
+        Activity::ActBuild(package_name, drv_path, host, _, _) => {
+            attributes.push(string_attribute("nix.package", package_name));
+            attributes.push(string_attribute("nix.drv_path", drv_path));
+            if !host.is_empty() {
+                attributes.push(string_attribute("nix.host", host));
+            }
+        }
+        Activity::ActSubstitute(package_name, store_path, host)
+        | Activity::ActQueryPathInfo(package_name, store_path, host) => {
+            attributes.push(string_attribute("nix.package", package_name));
+            attributes.push(string_attribute("nix.store_path", store_path));
+            attributes.push(string_attribute("nix.host", host));
+        }
+        Activity::ActCopyPath(package_name, store_path, from, to) => {
+            attributes.push(string_attribute("nix.package", package_name));
+            attributes.push(string_attribute("nix.store_path", store_path));
+            attributes.push(string_attribute("nix.host", from));
+            attributes.push(string_attribute("nix.copy.to", to));
+        }
+        Activity::ActFileTransfer(url) => attributes.push(string_attribute("url.full", url)),
+        Activity::ActPostBuildHook(store_path) => {
+            attributes.push(string_attribute("nix.drv_path", store_path))
+        }
+        _ => {}
+    }
+    attributes
+}
+
+fn span_name(activity: &Activity, text: &str) -> String {
+    match activity {
+        Activity::ActBuild(package_name, ..) => format!("build {}", package_name),
+        Activity::ActSubstitute(package_name, ..) => format!("substitute {}", package_name),
+        Activity::ActCopyPath(package_name, ..) => format!("copy {}", package_name),
+        _ if !text.is_empty() => text.to_owned(),
+        other => activity_type_name(other).to_owned(),
+    }
+}
+
+/// An OTLP/JSON trace of the run: a root span for the command and a span per activity.
+pub fn trace(state: &CommandState, command: &str, status: &ExitStatus) -> Value {
+    let ids = Ids {
+        hasher: RandomState::new(),
+    };
+    let trace_id = ids.trace_id();
+    let end = state.end.unwrap_or(SystemTime::now());
+    let mut root = json!({
+        "traceId": trace_id,
+        "spanId": ids.span_id(None),
+        "name": command,
+        "kind": SPAN_KIND_INTERNAL,
+        "startTimeUnixNano": nanos(state.start),
+        "endTimeUnixNano": nanos(end),
+        "attributes": [
+            string_attribute("process.command_line", command),
+            int_attribute("process.exit.code", status.code().unwrap_or(-1) as i64),
+        ],
+    });
+    if !status.success() {
+        root["status"] = json!({ "code": STATUS_ERROR, "message": "nix failed" });
+    }
+    let mut spans = vec![root];
+    let mut activities: Vec<(&i64, _)> = state.activity.iter().collect();
+    activities.sort_by_key(|(id, _)| **id);
+    for (id, act) in activities {
+        // parents nix never started (or we never saw) hang from the command
+        let parent = act.parent.filter(|p| state.activity.contains_key(p));
+        let act_end = act.end.unwrap_or(end);
+        let mut attributes = activity_attributes(&act.activity);
+        if let Some(phase) = &act.phase {
+            attributes.push(string_attribute("nix.phase", phase));
+        }
+        if let Some(progress) = act.progress {
+            attributes.push(int_attribute("nix.bytes", progress.done));
+            if progress.expected > 0 {
+                attributes.push(int_attribute("nix.bytes.expected", progress.expected));
+            }
+        }
+        let events: Vec<Value> = act
+            .phases
+            .iter()
+            .map(|(phase, t)| json!({ "name": phase, "timeUnixNano": nanos(*t) }))
+            .collect();
+        let mut span = json!({
+            "traceId": trace_id,
+            "spanId": ids.span_id(Some(*id)),
+            "parentSpanId": ids.span_id(parent),
+            "name": span_name(&act.activity, &act.text),
+            "kind": SPAN_KIND_INTERNAL,
+            "startTimeUnixNano": nanos(act.start),
+            "endTimeUnixNano": nanos(act_end),
+            "attributes": attributes,
+            "events": events,
+        });
+        if let Activity::ActBuild(_, drv_path, ..) = &act.activity {
+            if let Some(err) = state
+                .errors
+                .iter()
+                .find(|e| e.drv_path() == Some(drv_path.as_str()))
+            {
+                span["status"] = json!({ "code": STATUS_ERROR, "message": err.summary() });
+            }
+        }
+        spans.push(span);
+    }
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    string_attribute("service.name", "nixv"),
+                    string_attribute("service.version", env!("CARGO_PKG_VERSION")),
+                ]
+            },
+            "scopeSpans": [{
+                "scope": { "name": "nixv", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }]
+        }]
+    })
+}
+
+pub fn write_trace(file: &Path, trace: &Value) -> Result<(), Error> {
+    fs::write(file, serde_json::to_string(trace)?)
+}
+
+/// Posts the trace to an OTLP/HTTP collector, `http://host:port` gets `/v1/traces` appended.
+pub fn send_trace(endpoint: &str, trace: &Value) -> Result<(), Error> {
+    let rest = endpoint.strip_prefix("http://").ok_or(Error::new(
+        ErrorKind::InvalidInput,
+        format!(
+            "only http:// OTLP endpoints are supported, got {}",
+            endpoint
+        ),
+    ))?;
+    let (host, path) = match rest.split_once('/') {
+        Some((host, "")) | Some((host, "v1/traces")) => (host, String::from("/v1/traces")),
+        Some((host, path)) => (host, format!("/{}", path)),
+        None => (rest, String::from("/v1/traces")),
+    };
+    let address = match host.contains(':') {
+        true => host.to_owned(),
+        false => format!("{}:4318", host),
+    };
+    let body = serde_json::to_string(trace)?;
+    // an unreachable collector must not hold the end of the run for the OS connect timeout
+    let mut last_err = Error::new(
+        ErrorKind::NotFound,
+        format!("{} resolves to no address", address),
+    );
+    let mut connected = None;
+    for socket_address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_address, TIMEOUT) {
+            Ok(stream) => {
+                connected = Some(stream);
+                break;
+            }
+            Err(err) => last_err = err,
+        }
+    }
+    let mut stream = connected.ok_or(last_err)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(Error::other(format!(
+            "the collector answered: {}",
+            status_line
+        ))),
+    }
+}
@@ -0,0 +1,190 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::nix_logs::helpers::print_human;
+
+use super::types::JSONCommandState;
+
+/// Build time rises above the threshold are only flagged past that, to leave out the noise of
+/// short builds.
+const MIN_SLOWDOWN: Duration = Duration::from_secs(5);
+pub const DEFAULT_THRESHOLD_PERCENT: u32 = 50;
+
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub enum RegressionKind {
+    Slower {
+        baseline: Duration,
+        current: Duration,
+    },
+    /// substituted in the baseline, built locally now
+    NowBuilt { current: Duration },
+}
+
+/// A derivation, keyed by its name and version so that it survives changes of its hash.
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Regression {
+    pub package_name: String,
+    pub kind: RegressionKind,
+}
+
+/// The longest build of every package name and version of a run.
+fn build_times(state: &JSONCommandState) -> HashMap<&str, Duration> {
+    let mut times: HashMap<&str, Duration> = HashMap::new();
+    for build in state.act_build.iter() {
+        let duration = build.end.duration_since(build.start).unwrap_or_default();
+        let time = times.entry(build.package_name.as_str()).or_default();
+        *time = (*time).max(duration);
+    }
+    times
+}
+
+/// Builds of `current` slower than in `baseline` by more than `threshold_percent`, and the
+/// derivations `baseline` substituted that `current` built.
+pub fn compare(
+    baseline: &JSONCommandState,
+    current: &JSONCommandState,
+    threshold_percent: u32,
+) -> Vec<Regression> {
+    let baseline_times = build_times(baseline);
+    let mut regressions = Vec::new();
+    let mut current_times: Vec<(&str, Duration)> = build_times(current).into_iter().collect();
+    current_times.sort();
+    for (package_name, current) in current_times {
+        let kind = match baseline_times.get(package_name) {
+            Some(baseline) => {
+                let limit = baseline.mul_f64(1.0 + threshold_percent as f64 / 100.0);
+                match current > limit && current - *baseline >= MIN_SLOWDOWN {
+                    true => RegressionKind::Slower {
+                        baseline: *baseline,
+                        current,
+                    },
+                    false => continue,
+                }
+            }
+            None => {
+                let substituted = baseline
+                    .act_substitute
+                    .iter()
+                    .any(|s| s.package_name == package_name);
+                match substituted {
+                    true => RegressionKind::NowBuilt { current },
+                    false => continue,
+                }
+            }
+        };
+        regressions.push(Regression {
+            package_name: package_name.to_owned(),
+            kind,
+        });
+    }
+    regressions
+}
+
+pub fn print_regressions(run: &str, regressions: &[Regression]) {
+    if regressions.is_empty() {
+        print_human(format!("no regression against the baseline {}", run));
+        return;
+    }
+    print_human(format!(
+        "{} regression(s) against the baseline {}:",
+        regressions.len(),
+        run
+    ));
+    for regression in regressions {
+        match &regression.kind {
+            RegressionKind::Slower { baseline, current } => log::warn!(
+                "  {} took {:.1}s instead of {:.1}s (+{:.0}%)",
+                regression.package_name,
+                current.as_secs_f64(),
+                baseline.as_secs_f64(),
+                100.0 * (current.as_secs_f64() / baseline.as_secs_f64().max(0.001) - 1.0)
+            ),
+            RegressionKind::NowBuilt { current } => log::warn!(
+                "  {} was substituted, now built locally in {:.1}s",
+                regression.package_name,
+                current.as_secs_f64()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::nix_tracker::types::{CommandState, JSONActBuild, JSONActSubstitute};
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn run(builds: &[(&str, u64)], substitutes: &[&str]) -> JSONCommandState {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut state = CommandState::new();
+        state.end = Some(start);
+        let mut state = CommandState::to_json(state);
+        for (package_name, duration) in builds {
+            state.act_build.push(JSONActBuild {
+                id: 0,
+                package_name: package_name.to_string(),
+                store_path: format!("/nix/store/{}-{}.drv", state.act_build.len(), package_name),
+                host: String::new(),
+                start,
+                end: start + secs(*duration),
+                phases: Vec::new(),
+            });
+        }
+        for package_name in substitutes {
+            state.act_substitute.push(JSONActSubstitute {
+                id: 0,
+                package_name: package_name.to_string(),
+                store_path: format!("/nix/store/baseline-{}", package_name),
+                from: String::from("https://cache.nixos.org"),
+                start,
+                end: start,
+                size: None,
+            });
+        }
+        state
+    }
+
+    #[test]
+    fn compare_flags_builds_slower_than_the_threshold() {
+        let baseline = run(&[("hello-2.12.1", 10), ("zlib-1.3", 20)], &[]);
+        let current = run(&[("hello-2.12.1", 16), ("zlib-1.3", 28)], &[]);
+        assert_eq!(
+            compare(&baseline, &current, 50),
+            vec![Regression {
+                package_name: String::from("hello-2.12.1"),
+                kind: RegressionKind::Slower {
+                    baseline: secs(10),
+                    current: secs(16),
+                },
+            }]
+        );
+        assert_eq!(compare(&baseline, &current, 100), Vec::new());
+    }
+
+    #[test]
+    fn compare_leaves_out_slowdowns_under_the_minimum() {
+        let baseline = run(&[("hello-2.12.1", 1)], &[]);
+        let current = run(&[("hello-2.12.1", 5)], &[]);
+        // five times slower, but only by 4s
+        assert_eq!(compare(&baseline, &current, 50), Vec::new());
+    }
+
+    #[test]
+    fn compare_flags_substituted_packages_now_built_by_name() {
+        let baseline = run(&[], &["zlib-1.3", "openssl-3.0.13"]);
+        let current = run(&[("zlib-1.3", 3), ("hello-2.12.1", 40)], &[]);
+        assert_eq!(
+            compare(&baseline, &current, 50),
+            vec![Regression {
+                package_name: String::from("zlib-1.3"),
+                kind: RegressionKind::NowBuilt { current: secs(3) },
+            }]
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::{fs, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::nix_logs::{errors::NixError, helpers::print_human, types::Activity};
+
+use super::types::CommandState;
+
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub enum ReproOutcome {
+    Reproducible,
+    NotReproducible {
+        output: String,
+        check_path: Option<String>,
+        diff: Option<String>,
+    },
+    NotChecked(String),
+    Failed,
+}
+
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ReproDerivation {
+    pub package_name: String,
+    pub drv_path: String,
+    pub host: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub outcome: ReproOutcome,
+}
+
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ReproReport {
+    pub installable: Vec<String>,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub derivations: Vec<ReproDerivation>,
+}
+
+impl ReproReport {
+    pub fn from_state(installable: Vec<String>, state: &CommandState) -> ReproReport {
+        let mut derivations: Vec<ReproDerivation> = state
+            .activity
+            .values()
+            .filter_map(|act| match &act.activity {
+                Activity::ActBuild(package_name, drv_path, host, _, _) => {
+                    let outcome = match outcome_for(drv_path, &state.errors) {
+                        Some(outcome) => outcome,
+                        None if act.text.starts_with("checking outputs of") => {
+                            ReproOutcome::Reproducible
+                        }
+                        None => ReproOutcome::NotChecked(String::from("built for the first time")),
+                    };
+                    Some(ReproDerivation {
+                        package_name: package_name.to_owned(),
+                        drv_path: drv_path.to_owned(),
+                        host: host.to_owned(),
+                        start: act.start,
+                        end: act.end.unwrap_or(SystemTime::now()),
+                        outcome,
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        derivations.sort_by_key(|d| d.start);
+        ReproReport {
+            installable,
+            start: state.start,
+            end: state.end.unwrap_or(SystemTime::now()),
+            derivations,
+        }
+    }
+
+    /// Whether any derivation was rebuilt and compared with its previous outputs.
+    pub fn checked_any(&self) -> bool {
+        self.derivations.iter().any(|d| {
+            matches!(
+                d.outcome,
+                ReproOutcome::Reproducible | ReproOutcome::NotReproducible { .. }
+            )
+        })
+    }
+
+    /// Only a run that checked something can pass, derivations built for the first time prove
+    /// nothing.
+    pub fn is_reproducible(&self) -> bool {
+        self.checked_any()
+            && self.derivations.iter().all(|d| {
+                matches!(
+                    d.outcome,
+                    ReproOutcome::Reproducible | ReproOutcome::NotChecked(_)
+                )
+            })
+    }
+
+    pub fn print_summary(&self) {
+        for d in &self.derivations {
+            match &d.outcome {
+                ReproOutcome::Reproducible => log::info!("reproducible: {}", d.package_name),
+                ReproOutcome::NotReproducible {
+                    output,
+                    check_path,
+                    diff,
+                } => {
+                    log::error!("not reproducible: {} ({})", d.package_name, output);
+                    match (check_path, diff) {
+                        (_, Some(diff)) => log::error!("  diff written to {}", diff),
+                        (Some(check_path), None) => {
+                            log::error!("  compare {} with {}", output, check_path)
+                        }
+                        (None, None) => log::error!(
+                            "  rerun with --keep-failed to keep the .check output for {}",
+                            d.drv_path
+                        ),
+                    }
+                }
+                ReproOutcome::NotChecked(reason) => {
+                    log::warn!("not checked: {} ({})", d.package_name, reason)
+                }
+                ReproOutcome::Failed => log::error!("failed to build: {}", d.package_name),
+            }
+        }
+        let reproducible = self
+            .derivations
+            .iter()
+            .filter(|d| d.outcome == ReproOutcome::Reproducible)
+            .count();
+        print_human(format!(
+            "{}/{} derivations reproducible",
+            reproducible,
+            self.derivations.len()
+        ));
+        if !self.checked_any() {
+            log::error!("nothing was checked: no derivation was rebuilt and compared");
+        }
+    }
+
+    pub fn dump_to_file(&self, file_name: &str) {
+        let written = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::from)
+            .and_then(|json_dump| fs::write(file_name, json_dump));
+        if let Err(err) = written {
+            log::warn!("unable to write the report to {}: {}", file_name, err);
+        }
+    }
+}
+
+fn outcome_for(drv_path: &str, errors: &[NixError]) -> Option<ReproOutcome> {
+    errors.iter().find_map(|err| match err {
+        NixError::NotDeterministic {
+            drv_path: d,
+            output,
+            check_path,
+            ..
+        } if d == drv_path => Some(ReproOutcome::NotReproducible {
+            output: output.to_owned(),
+            check_path: check_path.to_owned(),
+            diff: None,
+        }),
+        NixError::BuilderFailed { drv_path: d, .. } if d == drv_path => Some(ReproOutcome::Failed),
+        NixError::Other(msg)
+            if msg.contains(drv_path) && msg.contains("checking is not possible") =>
+        {
+            Some(ReproOutcome::NotChecked(String::from(
+                "outputs not valid before the check",
+            )))
+        }
+        _ => None,
+    })
+}
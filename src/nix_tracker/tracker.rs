@@ -0,0 +1,96 @@
+use std::{
+    sync::mpsc::{channel, Receiver, Sender},
+    time::SystemTime,
+};
+
+use crate::nix_logs::{parser::parse, process_logs::process_log, types::JSONMessage};
+
+use super::{
+    events::{events_for, Event},
+    types::{CommandState, JSONCommandState},
+};
+
+type Subscriber = Box<dyn FnMut(&Event) + Send>;
+
+/// Follows the internal-json log of one nix command.
+///
+/// Feed it the lines nix writes to stderr (or messages parsed elsewhere), every
+/// message updates the [`CommandState`] and is turned into [`Event`]s for the
+/// subscribers. Human readable output only goes through the `log` crate, so the
+/// embedding program decides whether and where it is printed.
+pub struct Tracker {
+    state: CommandState,
+    subscribers: Vec<Subscriber>,
+    senders: Vec<Sender<Event>>,
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tracker {
+    pub fn new() -> Tracker {
+        Tracker {
+            state: CommandState::new(),
+            subscribers: Vec::new(),
+            senders: Vec::new(),
+        }
+    }
+
+    /// Calls `callback` with every event, on the thread feeding the tracker.
+    pub fn subscribe<F: FnMut(&Event) + Send + 'static>(&mut self, callback: F) {
+        self.subscribers.push(Box::new(callback));
+    }
+
+    /// A channel receiving every event, dropped receivers are forgotten.
+    pub fn channel(&mut self) -> Receiver<Event> {
+        let (sender, receiver) = channel();
+        self.senders.push(sender);
+        receiver
+    }
+
+    /// Feeds one line of `--log-format internal-json`, with or without the `@nix ` prefix.
+    pub fn feed_line(&mut self, line: &str) {
+        let (msg, id) = parse(line.trim_end());
+        self.feed(id, msg);
+    }
+
+    /// Feeds a parsed message, `id` is the activity id returned with it by [`parse`].
+    pub fn feed(&mut self, id: i64, msg: Option<JSONMessage>) {
+        if !self.subscribers.is_empty() || !self.senders.is_empty() {
+            for event in events_for(id, &msg, &self.state) {
+                self.publish(event);
+            }
+        }
+        process_log(id, msg, &mut self.state);
+    }
+
+    /// Sends an event to the subscribers, for events the log itself doesn't carry.
+    pub fn publish(&mut self, event: Event) {
+        for subscriber in self.subscribers.iter_mut() {
+            subscriber(&event);
+        }
+        self.senders
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+
+    /// The state so far.
+    pub fn state(&self) -> &CommandState {
+        &self.state
+    }
+
+    /// A copy of the state so far in its serialisable form, running activities end now.
+    pub fn snapshot(&self) -> JSONCommandState {
+        let mut state = self.state.clone();
+        state.end = Some(state.end.unwrap_or(SystemTime::now()));
+        CommandState::to_json(state)
+    }
+
+    /// Ends the run and hands over its state, the subscribers are dropped.
+    pub fn finish(mut self) -> CommandState {
+        self.state.end = Some(SystemTime::now());
+        self.state
+    }
+}
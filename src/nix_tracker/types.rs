@@ -1,11 +1,20 @@
 use std::{
     collections::{HashMap, HashSet},
+    path::PathBuf,
     time::SystemTime,
 };
 
 use serde::{Deserialize, Serialize};
 
-use crate::nix_logs::types::{Activity, ActivityProgress};
+use super::{
+    closure::ClosureReport,
+    disposition::DispositionReport,
+    eval::{EvalReport, EvalStats},
+};
+use crate::nix_logs::{
+    errors::NixError,
+    types::{Activity, ActivityProgress, MessageAction},
+};
 
 #[derive(Debug, Serialize, Clone)]
 
@@ -16,9 +25,14 @@ pub struct ActivityState {
     pub phase: Option<String>,
     pub progress: Option<ActivityProgress>,
     pub package_name: Option<String>,
+    pub text: String,
+    /// every phase entered and when, the last one is `phase`
+    pub phases: Vec<(String, SystemTime)>,
+    /// id of the activity this one runs under
+    pub parent: Option<i64>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Clone)]
 
 pub struct CommandState {
     pub activity: HashMap<i64, ActivityState>,
@@ -26,34 +40,68 @@ pub struct CommandState {
     pub running: HashSet<i64>,
     pub completed: HashSet<i64>,
     pub failed: HashSet<i64>,
+    pub messages: Vec<MessageAction>,
+    pub errors: Vec<NixError>,
+    pub first_realise: Option<SystemTime>,
+    pub eval_messages: Vec<String>,
+    pub eval_stats: Option<EvalStats>,
+    pub run_dir: Option<PathBuf>,
+    /// the nix command line, to find the previous runs of the same command
+    pub command: Vec<String>,
+    /// store paths nix printed on stdout, the outputs of a build
+    pub outputs: Vec<String>,
+    pub closure: Option<ClosureReport>,
+    /// log files that could not be opened or written to
+    pub log_write_failures: usize,
     pub start: SystemTime,
     pub end: Option<SystemTime>,
 }
 
 #[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
 pub struct JSONActCopyPath {
-    package_name: String,
-    store_path: String,
-    from: String,
-    to: String,
-    start: SystemTime,
-    end: SystemTime,
+    /// the id nix gave the activity, see `activity_parents`
+    #[serde(default)]
+    pub id: i64,
+    pub package_name: String,
+    pub store_path: String,
+    pub from: String,
+    pub to: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    /// bytes copied, from the progress nix reported
+    #[serde(default)]
+    pub size: Option<i64>,
+}
+
+#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+pub struct JSONPhase {
+    pub name: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
 }
 
 #[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
 pub struct JSONActBuild {
-    package_name: String,
-    store_path: String,
-    host: String,
-    start: SystemTime,
-    end: SystemTime,
+    /// the id nix gave the activity, see `activity_parents`
+    #[serde(default)]
+    pub id: i64,
+    pub package_name: String,
+    pub store_path: String,
+    pub host: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    #[serde(default)]
+    pub phases: Vec<JSONPhase>,
 }
 
 #[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
 pub struct JSONActFileTransfer {
-    file: String,
-    start: SystemTime,
-    end: SystemTime,
+    /// the id nix gave the activity, see `activity_parents`
+    #[serde(default)]
+    pub id: i64,
+    pub file: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
 }
 
 #[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
@@ -100,11 +148,17 @@ pub struct JSONActBuildWaiting {
 
 #[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
 pub struct JSONActSubstitute {
-    package_name: String,
-    store_path: String,
-    from: String,
-    start: SystemTime,
-    end: SystemTime,
+    /// the id nix gave the activity, see `activity_parents`
+    #[serde(default)]
+    pub id: i64,
+    pub package_name: String,
+    pub store_path: String,
+    pub from: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    /// size of the store path, from the copy that substituted it
+    #[serde(default)]
+    pub size: Option<i64>,
 }
 
 #[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
@@ -123,7 +177,7 @@ pub struct JSONActPostBuildHook {
     end: SystemTime,
 }
 
-#[derive(Debug, Eq, Serialize, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct JSONCommandState {
     pub act_unknown: Vec<JSONActUnknown>,
     pub act_copy_path: Vec<JSONActCopyPath>,
@@ -141,6 +195,49 @@ pub struct JSONCommandState {
     pub start: SystemTime,
     pub end: SystemTime,
     pub required_derivations: HashSet<String>,
+    #[serde(default)]
+    pub errors: Vec<NixError>,
+    #[serde(default)]
+    pub eval: Option<EvalReport>,
+    #[serde(default)]
+    pub command: Vec<String>,
+    #[serde(default)]
+    pub outputs: Vec<String>,
+    #[serde(default)]
+    pub closure: Option<ClosureReport>,
+    #[serde(default)]
+    pub dispositions: Option<DispositionReport>,
+    #[serde(default)]
+    pub log_write_failures: usize,
+    /// the activity each activity runs under, by id
+    #[serde(default)]
+    pub activity_parents: HashMap<i64, i64>,
+}
+
+fn progress_size(progress: &ActivityProgress) -> i64 {
+    match progress.expected {
+        0 => progress.done,
+        expected => expected,
+    }
+}
+
+/// Each phase lasts until the next one starts, the last until the build stops.
+fn phase_spans(phases: &[(String, SystemTime)], end: SystemTime) -> Vec<JSONPhase> {
+    phases
+        .iter()
+        .enumerate()
+        .map(|(i, (name, start))| JSONPhase {
+            name: name.to_owned(),
+            start: *start,
+            end: phases.get(i + 1).map(|(_, next)| *next).unwrap_or(end),
+        })
+        .collect()
+}
+
+impl Default for CommandState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CommandState {
@@ -151,6 +248,16 @@ impl CommandState {
             running: HashSet::new(),
             completed: HashSet::new(),
             failed: HashSet::new(),
+            messages: Vec::new(),
+            errors: Vec::new(),
+            first_realise: None,
+            eval_messages: Vec::new(),
+            eval_stats: None,
+            run_dir: None,
+            command: Vec::new(),
+            outputs: Vec::new(),
+            closure: None,
+            log_write_failures: 0,
             start: SystemTime::now(),
             end: None, // Initialize end as None by default
         }
@@ -169,36 +276,62 @@ impl CommandState {
         let mut act_query_path_info = Vec::new();
         let mut act_post_build_hook = Vec::new();
         let mut act_build_waiting = Vec::new();
-        for (_, act) in state.activity {
+        let eval = EvalReport::from_state(&state);
+        let dispositions = DispositionReport::from_state(&state);
+        let copied_sizes: HashMap<String, i64> = state
+            .activity
+            .values()
+            .filter_map(|act| match (&act.activity, act.progress) {
+                (Activity::ActCopyPath(_, store_path, _, _), Some(progress)) => {
+                    Some((store_path.to_owned(), progress_size(&progress)))
+                }
+                _ => None,
+            })
+            .collect();
+        let activity_parents = state
+            .activity
+            .iter()
+            .filter_map(|(id, act)| Some((*id, act.parent?)))
+            .collect();
+        for (id, act) in state.activity {
             let start = act.start;
             let end = act.end.unwrap_or(SystemTime::now());
             match act.activity {
                 Activity::ActCopyPath(package_name, store_path, from, to) => {
                     act_copy_path.push(JSONActCopyPath {
+                        id,
                         start,
                         end,
                         package_name,
                         store_path,
                         from,
                         to,
+                        size: act.progress.as_ref().map(progress_size),
                     })
                 }
                 Activity::ActBuild(package_name, store_path, host, _, _) => {
                     act_build.push(JSONActBuild {
+                        id,
                         start,
                         end,
                         package_name,
                         store_path,
                         host,
+                        phases: phase_spans(&act.phases, end),
                     })
                 }
-                Activity::ActFileTransfer(file) => {
-                    act_file_transfer.push(JSONActFileTransfer { start, end, file })
-                }
+                Activity::ActFileTransfer(file) => act_file_transfer.push(JSONActFileTransfer {
+                    id,
+                    start,
+                    end,
+                    file,
+                }),
                 Activity::ActSubstitute(package_name, store_path, from) => {
                     act_substitute.push(JSONActSubstitute {
+                        id,
                         start,
                         end,
+                        size: copied_sizes.get(&store_path).copied(),
                         package_name,
                         store_path,
                         from,
@@ -214,7 +347,7 @@ impl CommandState {
                     }),
                 Activity::ActPostBuildHook(store_path) => {
                     act_post_build_hook.push(JSONActPostBuildHook {
-                        store_path: store_path,
+                        store_path,
                         start,
                         end,
                     })
@@ -253,6 +386,14 @@ impl CommandState {
             start: state.start,
             end: state.end.unwrap(),
             required_derivations: state.required_derivations,
+            errors: state.errors,
+            eval: Some(eval),
+            command: state.command,
+            outputs: state.outputs,
+            closure: state.closure,
+            dispositions: Some(dispositions),
+            log_write_failures: state.log_write_failures,
+            activity_parents,
         }
     }
 }